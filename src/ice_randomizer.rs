@@ -1,14 +1,274 @@
 use random::Random;
+use scrypto::engine::scrypto_env::ScryptoVmV1Api;
 use scrypto::prelude::*;
 
 #[derive(NonFungibleData, ScryptoSbor, Debug)]
 struct RandomIceTicket {
     #[mutable]
     result: Option<NonFungibleLocalId>,
+    /// The account to `distribute()` the won ICE to.
+    depositor: Option<ComponentAddress>,
+    /// The prize has been handed out - the ticket can no longer be redeemed for anything.
+    #[mutable]
+    claimed: bool,
+    /// The claim deadline has passed and the unclaimed prizes were swept.
+    #[mutable]
+    expired: bool,
+    /// The drop round the ticket takes part in.
+    round: u32,
+    /// The deposit the ticket was bought in - all the tickets of a deposit belong to one depositor.
+    deposit: u32,
+    /// The depositor's identity badge, presented to `deposit_as()`.
+    identity: Option<NonFungibleGlobalId>,
+    /// Who brought the deposit.
+    referrer: Option<Referrer>,
+    /// The referral bonus has been booked - a ticket winning again after a melt back doesn't earn it twice.
+    #[mutable]
+    rewarded: bool,
+    /// The ticket is guaranteed to win in the next draw of its round.
+    allowlisted: bool,
+    /// Wallet display fields, kept in sync with `status`.
+    #[mutable]
+    name: String,
+    #[mutable]
+    key_image_url: Url,
+    #[mutable]
+    status: TicketStatus,
+    /// The draw the ticket won in.
+    #[mutable]
+    draw: Option<u32>,
+}
+
+#[derive(ScryptoSbor, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TicketStatus {
+    Pending,
+    Won,
+    /// The won ICE was melted back, the ticket is pending the draw again.
+    MeltedBack,
+    /// The prize has been handed out via `claim()` or `distribute()`.
+    Redeemed,
+}
+
+impl TicketStatus {
+    fn label(&self) -> &'static str {
+        return match self {
+            TicketStatus::Pending => "Pending",
+            TicketStatus::Won => "Won",
+            TicketStatus::MeltedBack => "Melted back",
+            TicketStatus::Redeemed => "Redeemed",
+        };
+    }
+
+    fn image_url(&self) -> Url {
+        let image = match self {
+            TicketStatus::Pending => "ticket-pending.png",
+            TicketStatus::Won => "ticket-won.png",
+            TicketStatus::MeltedBack => "ticket-melted.png",
+            TicketStatus::Redeemed => "ticket-redeemed.png",
+        };
+        return Url::of(format!("https://ice-rng.radix.live/image/{}", image));
+    }
+}
+
+#[derive(NonFungibleData, ScryptoSbor, Debug)]
+struct MeltReceipt {
+    /// The ICE to be melted into WATER once the RRC404 cooldown is over.
+    ice_ids: Vec<NonFungibleLocalId>,
+}
+
+/// Commemorates taking part in a drop round, minted on redemption.
+#[derive(NonFungibleData, ScryptoSbor, Debug)]
+struct ParticipantBadge {
+    round: u32,
+    /// The number of tickets redeemed.
+    tickets: u32,
+    /// The number of them that won ICE.
+    wins: u32,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct KeeperConfig {
+    /// `trigger_mint()` is allowed only when more than `threshold` tickets are pending.
+    pub threshold: u16,
+    /// Min number of seconds between two draws.
+    pub cooldown: i64,
+    /// The number of NFTs to mint per keeper-triggered draw.
+    pub batch_size: u8,
+    /// XRD paid to the caller of `trigger_mint()`.
+    pub bounty: Decimal,
+}
+
+/// Protocol fee, charged in WATER per ticket.
+#[derive(ScryptoSbor, Clone, Debug)]
+pub enum ProtocolFee {
+    /// A share of the ticket price, e.g. `0.01` for 1%.
+    Percent(Decimal),
+    /// A fixed amount of WATER.
+    Flat(Decimal),
+}
+
+#[derive(ScryptoSbor, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeStage {
+    /// The fee is paid on top of the ticket price.
+    Deposit,
+    /// The fee is deducted from the WATER refunded for unused tickets.
+    Withdraw,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct DrawRecord {
+    pub round_id: u32,
+    /// The number of NFTs minted in the draw.
+    pub minted: u32,
+    /// XRD locked from the fee vault to pay for the callback. The unused part is refunded to the vault.
+    pub fee: Decimal,
+    /// When the callback was executed (seconds since Unix epoch).
+    pub drawn_at: i64,
+    /// The rarity distribution per depositor. Empty unless the rarity-aware allocation is on.
+    pub tiers: Vec<TierCount>,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct TierCount {
+    pub depositor: Depositor,
+    pub tier: String,
+    pub count: u32,
+}
+
+/// Who a ticket is attributed to by the rarity-aware allocation.
+#[derive(ScryptoSbor, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Depositor {
+    /// The identity badge presented to `deposit_as()`.
+    Identity(NonFungibleGlobalId),
+    /// The account the tickets were bought for via `deposit_for()`.
+    Account(ComponentAddress),
+    /// Tickets bought before the allocation was turned on, rerolls and swaps - each deposit counts separately.
+    Deposit(u32),
+}
+
+/// Rarity-aware allocation: no depositor gets more than one top-tier ICE per draw (as far as possible).
+/// While it's on, deposits should be attributed - via `deposit_for()` or `deposit_as()`.
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct RarityConfig {
+    /// The index of the rarity field in the ICE data.
+    /// The field should be a string, an unsigned integer or an enum (its discriminator is the tier).
+    pub field_index: u8,
+    pub top_tiers: Vec<String>,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct AllowlistConfig {
+    /// The badge to present to `deposit_allowlisted()`.
+    pub badge: ResourceAddress,
+    /// Max number of guaranteed tickets in total.
+    pub max_guaranteed: u32,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct DepositLimits {
+    /// Max number of tickets bought in one call. `None` - unlimited.
+    pub max_per_call: Option<u32>,
+    /// Max number of tickets per depositor - the ones bought, less the refunded ones. `None` - unlimited.
+    /// When set, deposits are accepted only via `deposit_as()` (or `deposit_with_proof_as()` behind the deposit gate).
+    pub max_per_depositor: Option<u32>,
+    /// The NFT identifying a depositor, required by `deposit_as()`.
+    pub identity_badge: Option<ResourceAddress>,
+}
+
+#[derive(ScryptoSbor, Clone, Debug, PartialEq, Eq)]
+pub enum Referrer {
+    Account(ComponentAddress),
+    /// A referral code NFT, its rewards are claimed with a proof of the NFT.
+    Code(NonFungibleLocalId),
+}
+
+/// A token accepted by `deposit()` besides WATER.
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct PaymentToken {
+    /// The amount of the token per 1 WATER.
+    pub price: Decimal,
+    /// The component swapping the token into WATER, should have a `swap(bucket) -> Bucket` method.
+    pub provider: ComponentAddress,
+}
+
+/// Private drops: deposits are accepted only via `deposit_with_proof()`.
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct DepositGate {
+    /// The resource to present a proof of.
+    pub badge: ResourceAddress,
+    /// Max number of tickets per badge NFT. `None` - unlimited.
+    pub tickets_per_badge: Option<u32>,
+}
+
+#[derive(ScryptoSbor)]
+pub struct Round {
+    /// Stores the tickets of the round that have not participated in the draw yet.
+    /// The key is ordinal - in range [0, tickets_count).
+    pub tickets_by_idx: KeyValueStore<u16, u32>,
+    /// The number of tickets still pending draw.
+    pub tickets_count: u16,
+    /// WATER in the `water` vault that belongs to the round.
+    pub water: Decimal,
+    /// Deposits are accepted while the round is open.
+    pub open: bool,
+    /// Draws requested from .Random, but not executed yet.
+    pub pending_draws: u32,
+    /// IDs of the completed draws.
+    pub draws: Vec<u32>,
+    /// Round totals, final once `finalized_at` is set.
+    pub summary: CampaignSummary,
+    /// Allowlisted tickets, served before the rest of the pool in the next draw.
+    /// May contain the tickets that have been withdrawn since.
+    pub guaranteed: Vec<u32>,
+    /// Consolation tokens funded for the round, less the ones paid out or withdrawn.
+    pub consolation: Decimal,
+    /// Consolation tokens per ticket left unused at finalization.
+    pub consolation_share: Decimal,
+}
+
+/// The place of a pending ticket in its round's pool.
+#[derive(ScryptoSbor, Clone, Copy, Debug)]
+pub struct PoolSlot {
+    pub round_id: u32,
+    pub idx: u16,
+    /// The ticket's refund releases counters kept in its data (the depositor's limit, the referrals, the allowlist),
+    /// so the data is loaded only for such tickets.
+    pub tracked: bool,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct CampaignSummary {
+    /// WATER deposited for tickets (excluding the protocol fee).
+    pub deposited: Decimal,
+    /// ICE minted in all the draws.
+    pub minted: u32,
+    /// ICE melted back to WATER for the next draws.
+    pub melted: u32,
+    /// WATER refunded for unused tickets.
+    pub redeemed: Decimal,
+    /// When the campaign was finalized (seconds since Unix epoch).
+    pub finalized_at: Option<i64>,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CampaignFinalizedEvent {
+    pub round_id: u32,
+    pub summary: CampaignSummary,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct DrawEvent {
+    pub draw_id: u32,
+    pub round_id: u32,
+    pub minted: u32,
+    pub fee: Decimal,
+    /// XRD left in the fee vault after the lock (before the refund of the unused fee).
+    pub fee_balance: Decimal,
 }
 
 #[blueprint]
-#[types(u16, u32)]
+#[events(DrawEvent, CampaignFinalizedEvent)]
+#[types(u16, u32, Decimal, NonFungibleLocalId, NonFungibleGlobalId, ResourceAddress, DrawRecord, Round, PoolSlot, PaymentToken, Referrer)]
 mod ice {
     /* Rrc404 Component */
     extern_blueprint!(
@@ -53,6 +313,9 @@ mod ice {
         // "component_tdx_2_1czzxynn4m4snhattvdf6knlyfs3ss70yufj975uh2mdhp8jes938sd"
         // "component_sim1cqqqqqqqqyqszqgqqqqqqqgpqyqsqqqqxumnwqgqqqqqqycnf7v0gx"
     );
+    /// Max number of rolls to find a winner for a top-tier ICE.
+    const RARITY_PROBES: u16 = 50;
+
     const RANDOM_BADGE: ResourceManager = resource_manager!(
         "resource_rdx1thufp23mqn3hefdza383tk2fxs3rvwv97djzq8x5czzdqrgkc807wj"
         // "resource_tdx_2_1t59tdtsvv7sc0nej3z585w5nmqpq3z5cms7xdwvkyqaqreu9j3rvyu"
@@ -63,13 +326,63 @@ mod ice {
     enable_method_auth! {
         roles {
             random_provider => updatable_by: [];
+            keeper => updatable_by: [OWNER];
         },
         methods {
             deposit => PUBLIC;
+            deposit_for => PUBLIC;
+            deposit_to => PUBLIC;
             withdraw => PUBLIC;
+            claim => PUBLIC;
+            withdraw_as_water => PUBLIC;
+            redeem_receipts => PUBLIC;
+            get_summary => PUBLIC;
+            get_referrals => PUBLIC;
+            trigger_mint => PUBLIC;
+            reroll => PUBLIC;
+            deposit_swap => PUBLIC;
+            cancel_swap => PUBLIC;
+            deposit_allowlisted => PUBLIC;
+            deposit_with_proof => PUBLIC;
+            deposit_as => PUBLIC;
+            deposit_with_proof_as => PUBLIC;
+            claim_referral_rewards => PUBLIC;
             mint => restrict_to: [OWNER];
+            mint_round => restrict_to: [OWNER];
+            open_round => restrict_to: [OWNER];
+            close_round => restrict_to: [OWNER];
             melt => restrict_to: [OWNER];
+            set_keeper_config => restrict_to: [OWNER];
+            fund_bounty => restrict_to: [OWNER];
+            withdraw_bounty => restrict_to: [OWNER];
+            set_draw_fee => restrict_to: [OWNER];
+            fund_fees => restrict_to: [OWNER];
+            withdraw_fees => restrict_to: [OWNER];
+            set_protocol_fee => restrict_to: [OWNER];
+            collect_fees => restrict_to: [OWNER];
+            set_melt_cooldown => restrict_to: [OWNER];
+            set_claim_deadline => restrict_to: [OWNER];
+            sweep_unclaimed => restrict_to: [OWNER];
+            collect_unclaimed_ice => restrict_to: [OWNER];
+            stock_prizes => restrict_to: [OWNER];
+            withdraw_prizes => restrict_to: [OWNER];
+            set_reroll_fee => restrict_to: [OWNER];
+            set_rarity_config => restrict_to: [OWNER];
+            set_allowlist => restrict_to: [OWNER];
+            set_deposit_gate => restrict_to: [OWNER];
+            set_deposit_limits => restrict_to: [OWNER];
+            set_payment_token => restrict_to: [OWNER];
+            set_referral_bonus => restrict_to: [OWNER];
+            fund_referrals => restrict_to: [OWNER];
+            withdraw_referrals => restrict_to: [OWNER];
+            finalize => restrict_to: [OWNER];
+            fund_consolation => restrict_to: [OWNER];
+            withdraw_consolation => restrict_to: [OWNER];
+            set_participant_badges => restrict_to: [OWNER];
+            distribute => restrict_to: [OWNER, keeper];
+            shuffle_swaps => restrict_to: [OWNER, keeper];
             do_mint => restrict_to: [random_provider];
+            abort_mint => restrict_to: [random_provider];
         }
     }
 
@@ -78,39 +391,225 @@ mod ice {
 
         /// Ticket ID auto-increment.
         ticket_seq: u32,
-        /// Stores the tickets that have not participated in the draw yet.
-        /// The key is ordinal - in range [0, tickets_count).
-        tickets_by_idx: KeyValueStore<u16, u32>,
-        /// Reversed map - the key is ticket NFT id, the value is index bound by `tickets_count`.
-        tickets_id_to_idx: KeyValueStore<u32, u16>,
-        /// The number of tickets still pending draw.
-        tickets_count: u16,
+        /// Reversed map - the key is ticket NFT id, the value is its place in the round's `tickets_by_idx`.
+        tickets_id_to_idx: KeyValueStore<u32, PoolSlot>,
+        /// Drop rounds, the key is the round ID - in range [1, round_count].
+        rounds: KeyValueStore<u32, Round>,
+        round_count: u32,
+        /// The round used by `deposit()`, `mint()` and `trigger_mint()`.
+        current_round: u32,
 
         melt_list: Vec<u32>,
 
         water: Vault,
         ice: NonFungibleVault,
+
+        /// XRD used to pay keepers for `trigger_mint()` calls.
+        bounty: Vault,
+        /// Permissionless mint settings. `None` - only the Owner can mint.
+        keeper_config: Option<KeeperConfig>,
+        /// When the last draw was requested (seconds since Unix epoch).
+        last_draw_at: i64,
+
+        /// XRD used to pay for the .Random callbacks.
+        fee_vault: Vault,
+        /// `expected_fee` sent to .Random with each request.
+        expected_fee: u8,
+        /// XRD locked from `fee_vault` in each `do_mint()` callback.
+        draw_fee: Decimal,
+        /// Total XRD locked for callbacks - the upper bound of the XRD spent, as the unused fee is refunded.
+        /// The actual spending is the decrease of `fee_vault` beyond the Owner's withdrawals.
+        fees_locked: Decimal,
+        /// Completed draws, the key is the draw ID - in range [1, draw_count].
+        draws: KeyValueStore<u32, DrawRecord>,
+        draw_count: u32,
+
+        /// WATER collected as the protocol fee.
+        treasury: Vault,
+        protocol_fee: Option<ProtocolFee>,
+        fee_stage: FeeStage,
+
+        /// Melt receipts, issued by `withdraw_as_water()` while the won ICE is still on cooldown.
+        receipt_manager: ResourceManager,
+        /// ICE behind the outstanding melt receipts, kept out of the sweep.
+        receipt_ice: NonFungibleVault,
+        /// Min number of seconds after the last freeze before RRC404 allows melting.
+        melt_cooldown: i64,
+        /// When ICE was last frozen (seconds since Unix epoch).
+        last_freeze_at: i64,
+
+        /// Tickets can be redeemed until the deadline (seconds since Unix epoch). `None` - no deadline.
+        claim_deadline: Option<i64>,
+        /// ICE left unclaimed after the deadline.
+        ice_treasury: NonFungibleVault,
+        /// The next ticket ID to be marked as expired by `sweep_unclaimed()`.
+        expiry_cursor: u32,
+
+        /// The RRC404 component freezing WATER into ICE and melting it back.
+        /// `None` - the lottery mode: the draws hand out the NFTs from `prizes` and the payment goes to the treasury.
+        rrc404: Option<ComponentAddress>,
+        /// WATER per ticket - the amount RRC404 freezes into one NFT (and melts it back into),
+        /// or the ticket price in the lottery mode.
+        ticket_price: Decimal,
+        /// NFTs stocked by the Owner for the lottery mode.
+        prizes: NonFungibleVault,
+
+        /// WATER charged per rerolled ICE, goes to the treasury.
+        reroll_fee: Decimal,
+        /// The round holding the reroll tickets, created by the first `reroll()`. Closed for deposits.
+        reroll_round: Option<u32>,
+
+        /// ICE deposited by holders for a blind trade, waiting to be shuffled among the swap tickets.
+        swap_ice: NonFungibleVault,
+        /// The round holding the swap tickets, created by the first `deposit_swap()`. Closed for deposits.
+        /// Its `water` is the number of ICE in `swap_ice`.
+        swap_round: Option<u32>,
+        /// ICE won by the swap tickets, kept out of the sweep.
+        swapped_ice: NonFungibleVault,
+
+        /// Deposit ID auto-increment, each `mint_tickets()` call is a separate deposit.
+        deposit_seq: u32,
+        /// `None` - the ICE is allocated regardless of its rarity.
+        rarity: Option<RarityConfig>,
+
+        /// `None` - `deposit_allowlisted()` is disabled.
+        allowlist: Option<AllowlistConfig>,
+        /// The number of guaranteed tickets granted so far, less the refunded ones.
+        guaranteed_count: u32,
+
+        /// `None` - public deposits.
+        deposit_gate: Option<DepositGate>,
+        /// Tickets bought per badge NFT, the key is the badge local ID.
+        badge_tickets: KeyValueStore<NonFungibleLocalId, u32>,
+
+        deposit_limits: DepositLimits,
+        /// Tickets bought per depositor identity, less the refunded ones.
+        depositor_tickets: KeyValueStore<NonFungibleGlobalId, u32>,
+
+        /// Alternative payment tokens, the key is the token's resource address.
+        payment_tokens: KeyValueStore<ResourceAddress, PaymentToken>,
+
+        /// Tickets bought per referrer.
+        referrals: KeyValueStore<Referrer, u32>,
+        /// WATER rewarded to the referrer for each won ticket.
+        referral_bonus: Decimal,
+        /// The referral code NFTs. `None` - only accounts can refer.
+        referral_codes: Option<ResourceAddress>,
+        /// WATER funded by the Owner for the referral rewards.
+        referral_treasury: Vault,
+        /// Rewards earned, but not paid out yet.
+        referral_rewards: KeyValueStore<Referrer, Decimal>,
+        /// WATER backing `referral_rewards`.
+        referral_payouts: Vault,
+
+        /// Consolation tokens for the unused tickets, created by the first `fund_consolation()`.
+        /// All rounds are consoled with the same token.
+        consolation: Option<Vault>,
+
+        /// Participant badges, minted on redemption - one per round redeemed.
+        participant_manager: ResourceManager,
+        participant_badges: bool,
     }
 
     impl IceRandomizer {
         pub fn instantiate() -> (Global<IceRandomizer>, Bucket) {
-            debug!("LOG:IceRandomizer::instantiate()\n");
+            return Self::instantiate_with(RRC404.address(), WATER_RESOURCE.address(), ICE_RESOURCE.address(), Decimal::ONE);
+        }
+
+        /// Instantiates a randomizer for any RRC404 collection: `rrc404` should expose `freeze`/`melt`,
+        /// turning the fungible `water` into the `ice` NFTs and back, `units_per_nft` WATER per NFT.
+        /// A ticket costs `units_per_nft` WATER.
+        pub fn instantiate_with(rrc404: ComponentAddress, water: ResourceAddress, ice: ResourceAddress, units_per_nft: Decimal)
+            -> (Global<IceRandomizer>, Bucket) {
+            assert!(units_per_nft.is_positive(), "Units per NFT should be positive: {}", units_per_nft);
+            return Self::create(Some(rrc404), water, ice, units_per_nft);
+        }
+
+        /// Instantiates a lottery without RRC404: tickets are bought with the fungible `payment` (`price` tokens per ticket),
+        /// the draws hand out the `prizes` NFTs, stocked by the Owner via `stock_prizes()`.
+        pub fn instantiate_lottery(payment: ResourceAddress, prizes: ResourceAddress, price: Decimal)
+            -> (Global<IceRandomizer>, Bucket) {
+            assert!(price.is_positive(), "Ticket price should be positive: {}", price);
+            return Self::create(None, payment, prizes, price);
+        }
+
+        fn create(rrc404: Option<ComponentAddress>, water: ResourceAddress, ice: ResourceAddress, ticket_price: Decimal)
+            -> (Global<IceRandomizer>, Bucket) {
+            debug!("LOG:IceRandomizer::create() rrc404: {:?}\n", rrc404);
+            assert!(water.is_fungible(), "WATER must be a fungible resource: {:?}", water);
+            assert!(!ice.is_fungible(), "ICE must be a non-fungible resource: {:?}", ice);
 
             let (address_reservation, component_address) =
                 Runtime::allocate_component_address(<IceRandomizer>::blueprint_id());
 
             let owner_badge = Self::create_owner_badge();
-            let ticket_manager = Self::create_ticket_manager(component_address);
+            let owner = owner_badge.resource_address();
+            let ticket_manager = Self::create_ticket_manager(component_address, owner);
+            let receipt_manager = Self::create_receipt_manager(component_address, owner);
+            let participant_manager = Self::create_participant_manager(component_address, owner);
+
+            let rounds = KeyValueStore::new_with_registered_type();
+            rounds.insert(1u32, Self::new_round());
 
             let randomizer = Self {
                 ticket_manager,
                 ticket_seq: 1,
-                tickets_by_idx: KeyValueStore::new_with_registered_type(),
                 tickets_id_to_idx: KeyValueStore::new_with_registered_type(),
-                tickets_count: 0,
+                rounds,
+                round_count: 1,
+                current_round: 1,
                 melt_list: Vec::new(),
-                water: Vault::new(WATER_RESOURCE.address()),
-                ice: Vault::new(ICE_RESOURCE.address()).as_non_fungible(),
+                water: Vault::new(water),
+                ice: Vault::new(ice).as_non_fungible(),
+                bounty: Vault::new(XRD),
+                keeper_config: None,
+                last_draw_at: 0,
+                fee_vault: Vault::new(XRD),
+                expected_fee: 60u8,
+                draw_fee: Decimal::zero(),
+                fees_locked: Decimal::zero(),
+                draws: KeyValueStore::new_with_registered_type(),
+                draw_count: 0,
+                treasury: Vault::new(water),
+                protocol_fee: None,
+                fee_stage: FeeStage::Deposit,
+                receipt_manager,
+                receipt_ice: Vault::new(ice).as_non_fungible(),
+                melt_cooldown: 4 * 60 * 60,
+                last_freeze_at: 0,
+                claim_deadline: None,
+                ice_treasury: Vault::new(ice).as_non_fungible(),
+                expiry_cursor: 1,
+                rrc404,
+                ticket_price,
+                prizes: Vault::new(ice).as_non_fungible(),
+                reroll_fee: Decimal::zero(),
+                reroll_round: None,
+                swap_ice: Vault::new(ice).as_non_fungible(),
+                swap_round: None,
+                swapped_ice: Vault::new(ice).as_non_fungible(),
+                deposit_seq: 0,
+                rarity: None,
+                allowlist: None,
+                guaranteed_count: 0,
+                deposit_gate: None,
+                badge_tickets: KeyValueStore::new_with_registered_type(),
+                deposit_limits: DepositLimits {
+                    max_per_call: None,
+                    max_per_depositor: None,
+                    identity_badge: None,
+                },
+                depositor_tickets: KeyValueStore::new_with_registered_type(),
+                payment_tokens: KeyValueStore::new_with_registered_type(),
+                referrals: KeyValueStore::new_with_registered_type(),
+                referral_bonus: Decimal::zero(),
+                referral_codes: None,
+                referral_treasury: Vault::new(water),
+                referral_rewards: KeyValueStore::new_with_registered_type(),
+                referral_payouts: Vault::new(water),
+                consolation: None,
+                participant_manager,
+                participant_badges: false,
             }
                 .instantiate()
                 .prepare_to_globalize(
@@ -119,8 +618,22 @@ mod ice {
                     )
                 )
                 .with_address(address_reservation)
+                .metadata(metadata!(
+                    roles {
+                        metadata_setter => rule!(require(owner_badge.resource_address()));
+                        metadata_setter_updater => rule!(deny_all);
+                        metadata_locker => rule!(require(owner_badge.resource_address()));
+                        metadata_locker_updater => rule!(deny_all);
+                    },
+                    init {
+                        "name" => "Ice Randomizer", updatable;
+                        "description" => "Random mint of RRC404 NFTs", updatable;
+                        "icon_url" => Url::of("https://ice-rng.radix.live/image/ice-randomizer-icon.png"), updatable;
+                    }
+                ))
                 .roles(roles!(
                     random_provider => rule!(require(RANDOM_BADGE.address()));
+                    keeper => rule!(deny_all);
                 ))
                 .globalize();
             return (randomizer, owner_badge);
@@ -142,14 +655,21 @@ mod ice {
                 .into();
         }
 
-        fn create_ticket_manager(component_address: ComponentAddress) -> ResourceManager {
+        /// The ticket, receipt and participant metadata can be rebranded with the owner badge.
+        fn create_ticket_manager(component_address: ComponentAddress, owner_badge: ResourceAddress) -> ResourceManager {
             return ResourceBuilder::new_integer_non_fungible::<RandomIceTicket>(OwnerRole::Fixed(
                 rule!(require(global_caller(component_address)))))
                 .metadata(metadata!(
+                    roles {
+                        metadata_setter => rule!(require(owner_badge));
+                        metadata_setter_updater => rule!(deny_all);
+                        metadata_locker => rule!(require(owner_badge));
+                        metadata_locker_updater => rule!(deny_all);
+                    },
                     init {
-                        "name" => "IRAND", locked;
-                        "description" => "Ice Randomizer Ticket", locked;
-                        "icon_url" => Url::of("https://ice-rng.radix.live/image/ice-randomizer-icon.png"), locked;
+                        "name" => "IRAND", updatable;
+                        "description" => "Ice Randomizer Ticket", updatable;
+                        "icon_url" => Url::of("https://ice-rng.radix.live/image/ice-randomizer-icon.png"), updatable;
                     }
                 ))
                 .mint_roles(mint_roles! {
@@ -167,6 +687,90 @@ mod ice {
                 .create_with_no_initial_supply();
         }
 
+        fn create_receipt_manager(component_address: ComponentAddress, owner_badge: ResourceAddress) -> ResourceManager {
+            return ResourceBuilder::new_ruid_non_fungible::<MeltReceipt>(OwnerRole::Fixed(
+                rule!(require(global_caller(component_address)))))
+                .metadata(metadata!(
+                    roles {
+                        metadata_setter => rule!(require(owner_badge));
+                        metadata_setter_updater => rule!(deny_all);
+                        metadata_locker => rule!(require(owner_badge));
+                        metadata_locker_updater => rule!(deny_all);
+                    },
+                    init {
+                        "name" => "IRAND Melt Receipt", updatable;
+                        "description" => "Ice Randomizer Melt Receipt - redeemable for WATER", updatable;
+                        "icon_url" => Url::of("https://ice-rng.radix.live/image/ice-randomizer-icon.png"), updatable;
+                    }
+                ))
+                .mint_roles(mint_roles! {
+                    minter => rule!(require(global_caller(component_address)));
+                    minter_updater => rule!(deny_all);
+                })
+                .burn_roles(burn_roles! {
+                    burner => rule!(require(global_caller(component_address)));
+                    burner_updater => rule!(deny_all);
+                })
+                .create_with_no_initial_supply();
+        }
+
+        fn create_participant_manager(component_address: ComponentAddress, owner_badge: ResourceAddress) -> ResourceManager {
+            return ResourceBuilder::new_ruid_non_fungible::<ParticipantBadge>(OwnerRole::Fixed(
+                rule!(require(global_caller(component_address)))))
+                .metadata(metadata!(
+                    roles {
+                        metadata_setter => rule!(require(owner_badge));
+                        metadata_setter_updater => rule!(deny_all);
+                        metadata_locker => rule!(require(owner_badge));
+                        metadata_locker_updater => rule!(deny_all);
+                    },
+                    init {
+                        "name" => "IRAND Participant", updatable;
+                        "description" => "Ice Randomizer Participant Badge", updatable;
+                        "icon_url" => Url::of("https://ice-rng.radix.live/image/ice-randomizer-icon.png"), updatable;
+                    }
+                ))
+                .mint_roles(mint_roles! {
+                    minter => rule!(require(global_caller(component_address)));
+                    minter_updater => rule!(deny_all);
+                })
+                .create_with_no_initial_supply();
+        }
+
+        fn new_round() -> Round {
+            return Round {
+                tickets_by_idx: KeyValueStore::new_with_registered_type(),
+                tickets_count: 0,
+                water: Decimal::zero(),
+                open: true,
+                pending_draws: 0,
+                draws: Vec::new(),
+                summary: CampaignSummary {
+                    deposited: Decimal::zero(),
+                    minted: 0,
+                    melted: 0,
+                    redeemed: Decimal::zero(),
+                    finalized_at: None,
+                },
+                guaranteed: Vec::new(),
+                consolation: Decimal::zero(),
+                consolation_share: Decimal::zero(),
+            };
+        }
+
+        /// Starts a new round and makes it the current one. Previous rounds keep running.
+        pub fn open_round(&mut self) -> u32 {
+            self.round_count += 1;
+            self.rounds.insert(self.round_count, Self::new_round());
+            self.current_round = self.round_count;
+            return self.round_count;
+        }
+
+        /// Stops accepting deposits into the round. Draws and withdrawals are not affected.
+        pub fn close_round(&mut self, round_id: u32) {
+            self.round_mut(round_id).open = false;
+        }
+
         /** assumes positive dec */
         fn split_int_and_fraction(d: Decimal) -> (I192, I192) {
             let raw_num = d.0;
@@ -174,26 +778,358 @@ mod ice {
             return (raw_num / divisor, raw_num % divisor);
         }
 
-        pub fn deposit(&mut self, bucket: Bucket) -> Bucket {
-            let (quotient, remainder) = Self::split_int_and_fraction(bucket.amount());
-            assert!(remainder.is_zero(),
-                    "Please do not deposit fractional tokens. {}", bucket.amount()
+        /// The integer part of a (positive) amount.
+        fn whole(d: Decimal) -> u32 {
+            let (quotient, _) = Self::split_int_and_fraction(d);
+            return quotient.try_into().unwrap();
+        }
+
+        /// Buys tickets with WATER or a registered payment token.
+        /// Returns the tickets and the change (the unspent token and the WATER left after the swap).
+        /// The optional `referrer` is recorded on the tickets.
+        pub fn deposit(&mut self, bucket: Bucket, referrer: Option<Referrer>) -> (Bucket, Vec<Bucket>) {
+            return self.deposit_to(bucket, self.current_round, referrer);
+        }
+
+        pub fn deposit_to(&mut self, bucket: Bucket, round_id: u32, referrer: Option<Referrer>) -> (Bucket, Vec<Bucket>) {
+            self.assert_not_gated();
+            self.assert_not_limited();
+            self.assert_attributed();
+            let (water, change) = self.convert_payment(bucket);
+            let tickets_count = self.take_payment(water, round_id);
+            if let Some(referrer) = &referrer {
+                self.add_referrals(referrer, tickets_count);
+            }
+            return (self.mint_tickets(tickets_count, None, round_id, 0, None, referrer), change);
+        }
+
+        fn add_referrals(&mut self, referrer: &Referrer, tickets_count: u32) {
+            if let Referrer::Code(code) = referrer {
+                let codes = self.referral_codes.expect("Referral codes are not enabled.");
+                assert!(ResourceManager::from(codes).non_fungible_exists(code), "Unknown referral code: {}", code);
+            }
+            let referred = self.referrals.get(referrer).map(|count| *count).unwrap_or(0);
+            self.referrals.insert(referrer.clone(), referred + tickets_count);
+        }
+
+        /// Sets the WATER rewarded per won referred ticket and the referral code NFTs.
+        /// Only the codes earn the bonus: a depositor could name their own account as the referrer.
+        pub fn set_referral_bonus(&mut self, bonus: Decimal, codes: Option<ResourceAddress>) {
+            assert!(!bonus.is_negative(), "Referral bonus should not be negative.");
+            assert!(!bonus.is_positive() || codes.is_some(), "Referral rewards require referral codes.");
+            self.referral_bonus = bonus;
+            self.referral_codes = codes;
+        }
+
+        pub fn fund_referrals(&mut self, bucket: Bucket) {
+            self.referral_treasury.put(bucket);
+        }
+
+        pub fn withdraw_referrals(&mut self, amount: Decimal) -> Bucket {
+            return self.referral_treasury.take(amount);
+        }
+
+        /// Books the bonus for the referral code of a won ticket (once per ticket), while the referral treasury lasts.
+        /// Account referrers are only counted.
+        fn reward_referrer(&mut self, ticket_id: u32) {
+            let bonus = self.referral_bonus;
+            if !bonus.is_positive() || self.referral_treasury.amount() < bonus {
+                return;
+            }
+            let local_id = Self::ticket_local_id(ticket_id);
+            let data = self.ticket_data(&local_id);
+            if data.rewarded {
+                return;
+            }
+            if let Some(referrer @ Referrer::Code(_)) = data.referrer {
+                let earned = self.referral_rewards.get(&referrer).map(|amount| *amount).unwrap_or(Decimal::zero());
+                self.referral_rewards.insert(referrer, earned + bonus);
+                let reward = self.referral_treasury.take(bonus);
+                self.referral_payouts.put(reward);
+                self.ticket_manager.update_non_fungible_data(&local_id, "rewarded", true);
+            }
+        }
+
+        fn take_referral_rewards(&mut self, referrer: &Referrer) -> Bucket {
+            let earned = self.referral_rewards.remove(referrer).unwrap_or(Decimal::zero());
+            return self.referral_payouts.take(earned);
+        }
+
+        /// Claims the rewards earned by the referral code NFTs in the proof.
+        pub fn claim_referral_rewards(&mut self, proof: Proof) -> Bucket {
+            let codes = self.referral_codes.expect("Referral codes are not enabled.");
+            let checked = proof.check_with_message(codes, "Please present your referral codes.");
+            let mut rewards: Bucket = Bucket::new(self.referral_payouts.resource_address());
+            for code in checked.as_non_fungible().non_fungible_local_ids() {
+                rewards.put(self.take_referral_rewards(&Referrer::Code(code)));
+            }
+            return rewards;
+        }
+
+        /// Swaps a payment token into WATER via its swap provider.
+        /// Returns WATER for a whole number of tickets and the change. WATER is returned as is, with no change.
+        fn convert_payment(&mut self, mut bucket: Bucket) -> (Bucket, Vec<Bucket>) {
+            let resource = bucket.resource_address();
+            if resource == self.water.resource_address() {
+                return (bucket, Vec::new());
+            }
+            let token = self.payment_tokens.get(&resource)
+                .map(|token| token.clone())
+                .unwrap_or_else(|| panic!("Unsupported payment token: {:?}", resource));
+
+            let ticket_price = self.ticket_price + self.ticket_fee(FeeStage::Deposit);
+            let token_price = token.price * ticket_price;
+            let (quotient, _) = Self::split_int_and_fraction(bucket.amount() / token_price);
+            let tickets_count: u32 = quotient.try_into().unwrap();
+            assert!(tickets_count > 0, "Not enough to buy a ticket: {} (ticket price: {})", bucket.amount(), token_price);
+            let payment = bucket.take_advanced(
+                token_price * Decimal::from(tickets_count),
+                WithdrawStrategy::Rounded(RoundingMode::ToPositiveInfinity),
             );
 
-            self.water.put(bucket);
+            let provider: Global<AnyComponent> = Global::from(token.provider);
+            let mut water: Bucket = provider.call_raw("swap", scrypto_args!(payment));
+            assert_eq!(water.resource_address(), self.water.resource_address(), "The swap provider should return WATER.");
+            let required = ticket_price * Decimal::from(tickets_count);
+            assert!(water.amount() >= required, "The swap returned {} WATER, required: {}", water.amount(), required);
+            let surplus = water.take(water.amount() - required);
+            return (water, vec![bucket, surplus]);
+        }
+
+        /// Registers (or removes) an alternative payment token.
+        pub fn set_payment_token(&mut self, resource: ResourceAddress, token: Option<PaymentToken>) {
+            match token {
+                Some(token) => {
+                    assert!(token.price.is_positive(), "The price should be positive.");
+                    self.payment_tokens.insert(resource, token);
+                }
+                None => {
+                    self.payment_tokens.remove(&resource);
+                }
+            };
+        }
+
+        /// Same as `deposit()` for the holders of the allowlist badge: the tickets are guaranteed to win
+        /// in the next draw of the current round, as long as the `max_guaranteed` limit allows.
+        /// The allowlist badge also lets its holders through the deposit gate (without the per-badge limits).
+        pub fn deposit_allowlisted(&mut self, bucket: Bucket, proof: Proof) -> Bucket {
+            let config = self.allowlist.clone().expect("The allowlist is not set.");
+            proof.check_with_message(config.badge, "Please present the allowlist badge.");
+            self.assert_not_limited();
+            self.assert_attributed();
+
+            let round_id = self.current_round;
+            let tickets_count = self.take_payment(bucket, round_id);
+            let guaranteed = tickets_count.min(config.max_guaranteed.saturating_sub(self.guaranteed_count));
+            self.guaranteed_count += guaranteed;
+            return self.mint_tickets(tickets_count, None, round_id, guaranteed, None, None);
+        }
 
+        pub fn set_allowlist(&mut self, config: Option<AllowlistConfig>) {
+            self.allowlist = config;
+        }
+
+        /// Deposit for private drops: requires a proof of the badge set by the Owner.
+        /// With per-badge limits, the proof should be of exactly one badge NFT.
+        pub fn deposit_with_proof(&mut self, bucket: Bucket, proof: Proof) -> Bucket {
+            let gate = self.deposit_gate.clone().expect("Deposits are public, please use deposit().");
+            let checked = proof.check_with_message(gate.badge, "Please present the deposit badge.");
+            self.assert_not_limited();
+            self.assert_attributed();
+
+            let round_id = self.current_round;
+            let tickets_count = self.take_payment(bucket, round_id);
+            self.use_badge_limit(&gate, checked, tickets_count);
+            return self.mint_tickets(tickets_count, None, round_id, 0, None, None);
+        }
+
+        /// Same as `deposit_with_proof()` with the per-depositor limit (or for the rarity-aware allocation):
+        /// `proof` is of the deposit badge, `identity` - of a single identity badge NFT.
+        pub fn deposit_with_proof_as(&mut self, bucket: Bucket, proof: Proof, identity: Proof) -> Bucket {
+            let gate = self.deposit_gate.clone().expect("Deposits are public, please use deposit_as().");
+            let checked = proof.check_with_message(gate.badge, "Please present the deposit badge.");
+            let identity = self.identify(identity);
+
+            let round_id = self.current_round;
+            let tickets_count = self.take_payment(bucket, round_id);
+            self.use_badge_limit(&gate, checked, tickets_count);
+            self.use_depositor_limit(&identity, tickets_count);
+            return self.mint_tickets(tickets_count, None, round_id, 0, Some(identity), None);
+        }
+
+        fn use_badge_limit(&mut self, gate: &DepositGate, checked: CheckedProof, tickets_count: u32) {
+            if let Some(limit) = gate.tickets_per_badge {
+                let badge_ids = checked.as_non_fungible().non_fungible_local_ids();
+                assert_eq!(badge_ids.len(), 1, "Please present exactly one deposit badge.");
+                let badge_id = badge_ids.into_iter().next().unwrap();
+                let used = self.badge_tickets.get(&badge_id).map(|count| *count).unwrap_or(0);
+                assert!(used + tickets_count <= limit,
+                        "Badge {} can buy {} more tickets, requested: {}", badge_id, limit - used, tickets_count
+                );
+                self.badge_tickets.insert(badge_id, used + tickets_count);
+            }
+        }
+
+        pub fn set_deposit_gate(&mut self, gate: Option<DepositGate>) {
+            if let Some(DepositGate { badge, tickets_per_badge: Some(_) }) = &gate {
+                assert!(!badge.is_fungible(), "Per-badge limits require a non-fungible badge.");
+            }
+            self.deposit_gate = gate;
+        }
+
+        fn assert_not_gated(&self) {
+            assert!(self.deposit_gate.is_none(),
+                    "Deposits require a badge, please use deposit_with_proof() or deposit_with_proof_as()."
+            );
+        }
+
+        /// Deposit with the per-depositor limit (or for the rarity-aware allocation):
+        /// `identity` is a proof of a single identity badge NFT.
+        /// The limit counts the tickets bought by the depositor, less the refunded ones - withdrawing WATER frees the limit up.
+        pub fn deposit_as(&mut self, bucket: Bucket, identity: Proof) -> Bucket {
+            let identity = self.identify(identity);
+            self.assert_not_gated();
+
+            let round_id = self.current_round;
+            let tickets_count = self.take_payment(bucket, round_id);
+            self.use_depositor_limit(&identity, tickets_count);
+            return self.mint_tickets(tickets_count, None, round_id, 0, Some(identity), None);
+        }
+
+        fn identify(&self, identity: Proof) -> NonFungibleGlobalId {
+            assert!(self.deposit_limits.max_per_depositor.is_some() || self.rarity.is_some(),
+                    "Deposits are not limited per depositor, please use deposit()."
+            );
+            let badge = self.deposit_limits.identity_badge.expect("The identity badge is not set.");
+            let checked = identity.check_with_message(badge, "Please present a proof of your identity badge.");
+            let ids = checked.as_non_fungible().non_fungible_local_ids();
+            assert_eq!(ids.len(), 1, "Please present exactly one identity badge.");
+            return NonFungibleGlobalId::new(badge, ids.into_iter().next().unwrap());
+        }
+
+        fn use_depositor_limit(&mut self, identity: &NonFungibleGlobalId, tickets_count: u32) {
+            if let Some(max) = self.deposit_limits.max_per_depositor {
+                let used = self.depositor_tickets.get(identity).map(|count| *count).unwrap_or(0);
+                assert!(used + tickets_count <= max,
+                        "You can buy {} more tickets, requested: {}", max.saturating_sub(used), tickets_count
+                );
+                self.depositor_tickets.insert(identity.clone(), used + tickets_count);
+            }
+        }
+
+        /// The identity badge should be set explicitly: virtual accounts can't present a proof of their owner badge.
+        pub fn set_deposit_limits(&mut self, limits: DepositLimits) {
+            if let Some(badge) = limits.identity_badge {
+                assert!(!badge.is_fungible(), "The identity badge should be non-fungible.");
+            } else {
+                assert!(limits.max_per_depositor.is_none(), "The per-depositor limit requires an identity badge.");
+            }
+            self.deposit_limits = limits;
+        }
+
+        fn assert_attributed(&self) {
+            assert!(self.rarity.is_none(),
+                    "Deposits should be attributed for the rarity-aware allocation, please use deposit_for() or deposit_as()."
+            );
+        }
+
+        fn assert_not_limited(&self) {
+            assert!(self.deposit_limits.max_per_depositor.is_none(),
+                    "Deposits are limited per depositor, please use deposit_as() or deposit_with_proof_as()."
+            );
+        }
+
+        /// Buys tickets and sends them to the given accounts, `recipients` are `(account, tickets count)` pairs.
+        /// The account is recorded on the tickets, so the won ICE can be `distribute()`d to it later.
+        /// Returns the tickets that could not be deposited.
+        pub fn deposit_for(&mut self, bucket: Bucket, recipients: Vec<(ComponentAddress, u32)>) -> Bucket {
+            self.assert_not_gated();
+            self.assert_not_limited();
+            let round_id = self.current_round;
+            let tickets_count = self.take_payment(bucket, round_id);
+            let requested: u32 = recipients.iter().map(|(_, count)| *count).sum();
+            assert_eq!(requested, tickets_count,
+                    "Recipients should receive exactly {} tickets, got: {}", tickets_count, requested
+            );
+
+            let mut refunds: Bucket = Bucket::new(self.ticket_manager.address());
+            for (address, count) in recipients {
+                let tickets = self.mint_tickets(count, Some(address), round_id, 0, None, None);
+                let account: Global<Account> = Global::from(address);
+                let refund = account.try_deposit_or_refund(tickets, None);
+                if let Some(bucket) = refund {
+                    refunds.put(bucket);
+                }
+            }
+            return refunds;
+        }
+
+        /// Puts the payment into the `water` vault (minus the fee) and returns the number of tickets bought.
+        fn take_payment(&mut self, mut bucket: Bucket, round_id: u32) -> u32 {
+            {
+                let round = self.round(round_id);
+                assert!(round.summary.finalized_at.is_none(), "Deposits are closed - the round has been finalized.");
+                assert!(round.open, "Deposits are closed for round {}.", round_id);
+            }
+            if let Some(deadline) = self.claim_deadline {
+                assert!(Self::now() < deadline, "Deposits are closed since: {}", deadline);
+            }
+
+            let fee = self.ticket_fee(FeeStage::Deposit);
+            let price = self.ticket_price + fee;
+            let (quotient, _) = Self::split_int_and_fraction(bucket.amount() / price);
             let tickets_count: u32 = quotient.try_into().unwrap();
+            assert_eq!(price * Decimal::from(tickets_count), bucket.amount(),
+                    "Please do not deposit fractional tokens. {} (ticket price: {})", bucket.amount(), price
+            );
+            if let Some(max) = self.deposit_limits.max_per_call {
+                assert!(tickets_count <= max, "Max {} tickets per deposit, requested: {}", max, tickets_count);
+            }
+
+            if fee.is_positive() {
+                self.treasury.put(bucket.take(fee * Decimal::from(tickets_count)));
+            }
+            {
+                let mut round = self.round_mut(round_id);
+                round.water += bucket.amount();
+                round.summary.deposited += bucket.amount();
+            }
+            self.water.put(bucket);
+            return tickets_count;
+        }
 
+        /// Mints the tickets of one deposit, the first `guaranteed` of them are allowlisted.
+        fn mint_tickets(&mut self, tickets_count: u32, depositor: Option<ComponentAddress>, round_id: u32, guaranteed: u32,
+                        identity: Option<NonFungibleGlobalId>, referrer: Option<Referrer>) -> Bucket {
+            self.deposit_seq += 1;
             let mut tickets: Bucket = Bucket::new(self.ticket_manager.address());
             for i in 0..tickets_count {
                 let ticket_id = self.ticket_seq + i;
-                let local_id = NonFungibleLocalId::integer(ticket_id.into());
-                let ticket: Bucket = self.ticket_manager.mint_non_fungible(&local_id, RandomIceTicket {
-                    result: None
-                });
+                let local_id = Self::ticket_local_id(ticket_id);
+                let data = RandomIceTicket {
+                    result: None,
+                    depositor,
+                    claimed: false,
+                    expired: false,
+                    round: round_id,
+                    deposit: self.deposit_seq,
+                    allowlisted: i < guaranteed,
+                    identity: identity.clone(),
+                    referrer: referrer.clone(),
+                    rewarded: false,
+                    name: Self::ticket_name(ticket_id, TicketStatus::Pending),
+                    key_image_url: TicketStatus::Pending.image_url(),
+                    status: TicketStatus::Pending,
+                    draw: None,
+                };
+                let tracked = Self::is_tracked(&data);
+                let ticket: Bucket = self.ticket_manager.mint_non_fungible(&local_id, data);
                 tickets.put(ticket);
 
-                self.add_ticket(ticket_id);
+                self.add_ticket(round_id, ticket_id, tracked);
+                if i < guaranteed {
+                    self.round_mut(round_id).guaranteed.push(ticket_id);
+                }
             }
 
             self.ticket_seq += tickets_count;
@@ -201,78 +1137,791 @@ mod ice {
             return tickets;
         }
 
-        pub fn withdraw(&mut self, tickets: Bucket) -> (Bucket, Bucket) {
+        /// Returns the won ICE, the WATER for the unused tickets, the consolation tokens (if funded)
+        /// and the participant badges (if enabled).
+        pub fn withdraw(&mut self, tickets: Bucket) -> (Bucket, Bucket, Option<Bucket>, Option<Bucket>) {
             assert_eq!(tickets.resource_address(), self.ticket_manager.address(), "Withdrawal requires to burn your tickets.");
 
+            let (ice_ids, water_counts, win_counts) = self.redeem_tickets(tickets.as_non_fungible().non_fungible_local_ids(), true);
+            tickets.burn();
+            return self.pay_out(ice_ids, water_counts, win_counts);
+        }
+
+        /// Same as `withdraw()`, but the tickets are kept (as keepsakes) and marked as claimed instead of being burned.
+        pub fn claim(&mut self, proof: NonFungibleProof) -> (Bucket, Bucket, Option<Bucket>, Option<Bucket>) {
+            let checked = proof.check_with_message(self.ticket_manager.address(), "Claiming requires a proof of your tickets.");
+
+            let local_ids = checked.non_fungible_local_ids();
+            let (ice_ids, water_counts, win_counts) = self.redeem_tickets(local_ids.clone(), false);
+            for local_id in &local_ids {
+                self.ticket_manager.update_non_fungible_data(local_id, "claimed", true);
+                self.update_ticket_status(local_id, TicketStatus::Redeemed);
+            }
+            return self.pay_out(ice_ids, water_counts, win_counts);
+        }
+
+        /// Collects the won ICE and removes the unused tickets from the draw.
+        /// Returns the ICE IDs, the number of unused and won tickets per round. Claimed tickets are skipped (if allowed).
+        /// Tickets still pending the draw are resolved via `tickets_id_to_idx`, without loading their data
+        /// (unless the slot is `tracked` - then the depositor's limit and the guaranteed slot are freed up,
+        /// and the referral is uncounted).
+        /// After the claim deadline, only the shuffled swap tickets can be redeemed.
+        fn redeem_tickets(&mut self, local_ids: IndexSet<NonFungibleLocalId>, allow_claimed: bool)
+            -> (IndexSet<NonFungibleLocalId>, IndexMap<u32, u32>, IndexMap<u32, u32>) {
             let mut ice_ids: IndexSet<NonFungibleLocalId> = IndexSet::new();
-            let mut water_count = 0u8;
-            for non_fungible in tickets.as_non_fungible().non_fungibles::<RandomIceTicket>() {
-                let data = non_fungible.data();
+            let mut water_counts: IndexMap<u32, u32> = IndexMap::new();
+            let mut win_counts: IndexMap<u32, u32> = IndexMap::new();
+            for local_id in local_ids {
+                let id = Self::ticket_id(&local_id);
+                if let Some(slot) = self.tickets_id_to_idx.get(&id).map(|slot| *slot) {
+                    self.assert_not_expired();
+                    if slot.tracked {
+                        self.release_counters(&local_id);
+                    }
+                    let round_id = self.remove_ticket(id);
+                    assert_ne!(Some(round_id), self.swap_round,
+                               "Swap ticket {} can be redeemed after the shuffle, or cancelled via cancel_swap().", local_id
+                    );
+                    *water_counts.entry(round_id).or_insert(0) += 1;
+                    continue;
+                }
+
+                let data = self.ticket_data(&local_id);
+                if self.swap_round != Some(data.round) {
+                    self.assert_not_expired();
+                }
+                if data.claimed {
+                    assert!(allow_claimed, "Ticket {} has already been claimed.", local_id);
+                    continue;
+                }
                 match data.result {
                     Some(ice_id) => {
+                        if self.swap_round == Some(data.round) {
+                            let ice = self.swapped_ice.take_non_fungible(&ice_id);
+                            self.ice.put(ice);
+                        }
                         ice_ids.insert(ice_id);
+                        *win_counts.entry(data.round).or_insert(0) += 1;
                     }
                     None => {
-                        water_count += 1;
-                        let id = match non_fungible.local_id() {
-                            NonFungibleLocalId::Integer(int_id) => int_id.value() as u32,
-                            _ => u32::MAX,
-                        };
-                        self.remove_ticket(id);
+                        panic!("Not possible");
                     }
                 };
             }
+            return (ice_ids, water_counts, win_counts);
+        }
+
+        /// Releases the counters kept for a refunded pending ticket.
+        fn release_counters(&mut self, local_id: &NonFungibleLocalId) {
+            let data = self.ticket_data(local_id);
+            if let Some(identity) = data.identity.filter(|_| self.deposit_limits.max_per_depositor.is_some()) {
+                if let Some(mut count) = self.depositor_tickets.get_mut(&identity) {
+                    *count = count.saturating_sub(1);
+                }
+            }
+            if let Some(referrer) = data.referrer {
+                if let Some(mut count) = self.referrals.get_mut(&referrer) {
+                    *count = count.saturating_sub(1);
+                }
+            }
+            if data.allowlisted {
+                self.guaranteed_count = self.guaranteed_count.saturating_sub(1);
+            }
+        }
+
+        fn is_tracked(data: &RandomIceTicket) -> bool {
+            return data.identity.is_some() || data.referrer.is_some() || data.allowlisted;
+        }
+
+        fn pay_out(&mut self, ice_ids: IndexSet<NonFungibleLocalId>, water_counts: IndexMap<u32, u32>, win_counts: IndexMap<u32, u32>)
+            -> (Bucket, Bucket, Option<Bucket>, Option<Bucket>) {
+            let badges = self.mint_participant_badges(&water_counts, &win_counts);
+            let (water, consolation) = self.refund_water(water_counts);
+            return (self.ice.take_non_fungibles(&ice_ids).into(), water, consolation, badges);
+        }
+
+        /// Mints a participant badge per redeemed round, if enabled.
+        fn mint_participant_badges(&mut self, water_counts: &IndexMap<u32, u32>, win_counts: &IndexMap<u32, u32>) -> Option<Bucket> {
+            if !self.participant_badges {
+                return None;
+            }
+            let rounds: IndexSet<u32> = water_counts.keys().chain(win_counts.keys()).copied().collect();
+            let mut badges: Bucket = Bucket::new(self.participant_manager.address());
+            for round in rounds {
+                let wins = win_counts.get(&round).copied().unwrap_or(0);
+                let tickets = wins + water_counts.get(&round).copied().unwrap_or(0);
+                badges.put(self.participant_manager.mint_ruid_non_fungible(ParticipantBadge { round, tickets, wins }));
+            }
+            return Some(badges);
+        }
+
+        pub fn set_participant_badges(&mut self, enabled: bool) {
+            self.participant_badges = enabled;
+        }
+
+        /// Refunds WATER for the unused tickets, plus the consolation share for the ones of finalized rounds.
+        fn refund_water(&mut self, water_counts: IndexMap<u32, u32>) -> (Bucket, Option<Bucket>) {
+            let price = self.ticket_price;
+            let mut water_count = 0u32;
+            let mut shares: Vec<(u32, Decimal)> = Vec::new();
+            for (round_id, count) in water_counts {
+                let mut round = self.round_mut(round_id);
+                round.water -= price * Decimal::from(count);
+                round.summary.redeemed += price * Decimal::from(count);
+                if round.summary.finalized_at.is_some() && round.consolation_share.is_positive() {
+                    shares.push((round_id, round.consolation_share * Decimal::from(count)));
+                }
+                water_count += count;
+            }
+            let mut water = self.water.take(price * Decimal::from(water_count));
+            let fee = self.ticket_fee(FeeStage::Withdraw) * Decimal::from(water_count);
+            if fee.is_positive() {
+                self.treasury.put(water.take(fee));
+            }
+            // the rounding dust stays with the round, see `withdraw_consolation()`
+            let consolation = match &mut self.consolation {
+                Some(vault) => {
+                    let mut tokens = Bucket::new(vault.resource_address());
+                    for (round_id, share) in shares {
+                        let paid = vault.take_advanced(share, WithdrawStrategy::Rounded(RoundingMode::ToZero));
+                        self.rounds.get_mut(&round_id).unwrap().consolation -= paid.amount();
+                        tokens.put(paid);
+                    }
+                    Some(tokens)
+                }
+                None => None,
+            };
+            return (water, consolation);
+        }
+
+        /// Redeems the tickets for WATER only - the won ICE is melted back via RRC404.
+        /// While the ICE is on cooldown, it stays in the component and a melt receipt is returned instead,
+        /// which can be exchanged for WATER with `redeem_receipts()` later.
+        pub fn withdraw_as_water(&mut self, tickets: Bucket) -> (Bucket, Bucket, Option<Bucket>, Option<Bucket>) {
+            assert_eq!(tickets.resource_address(), self.ticket_manager.address(), "Withdrawal requires to burn your tickets.");
+
+            let (ice_ids, water_counts, win_counts) = self.redeem_tickets(tickets.as_non_fungible().non_fungible_local_ids(), true);
+            tickets.burn();
+
+            let badges = self.mint_participant_badges(&water_counts, &win_counts);
+            let (mut water, consolation) = self.refund_water(water_counts);
+            let mut receipt: Bucket = Bucket::new(self.receipt_manager.address());
+            if !ice_ids.is_empty() {
+                if self.can_melt() {
+                    water.put(self.melt_ice(ice_ids));
+                } else {
+                    let ice = self.ice.take_non_fungibles(&ice_ids);
+                    self.receipt_ice.put(ice);
+                    receipt.put(self.receipt_manager.mint_ruid_non_fungible(MeltReceipt {
+                        ice_ids: ice_ids.into_iter().collect()
+                    }));
+                }
+            }
+            return (water, receipt, consolation, badges);
+        }
+
+        /// Exchanges melt receipts for WATER. The tickets behind them are burned, so the receipts don't expire.
+        pub fn redeem_receipts(&mut self, receipts: Bucket) -> Bucket {
+            assert_eq!(receipts.resource_address(), self.receipt_manager.address(), "Please provide melt receipts.");
+            assert!(self.can_melt(), "ICE can be melted after: {}", self.last_freeze_at + self.melt_cooldown);
+
+            let mut ice_ids: IndexSet<NonFungibleLocalId> = IndexSet::new();
+            for receipt in receipts.as_non_fungible().non_fungibles::<MeltReceipt>() {
+                ice_ids.extend(receipt.data().ice_ids);
+            }
+            receipts.burn();
+            let ice_bucket = self.receipt_ice.take_non_fungibles(&ice_ids).into();
+            return self.rrc404().melt(ice_bucket);
+        }
+
+        pub fn set_melt_cooldown(&mut self, melt_cooldown: i64) {
+            assert!(melt_cooldown >= 0, "Cooldown should not be negative.");
+            self.melt_cooldown = melt_cooldown;
+        }
+
+        /// Sets the claim deadline. Once set, it can only be extended (or removed) until it passes.
+        pub fn set_claim_deadline(&mut self, claim_deadline: Option<i64>) {
+            let now = Self::now();
+            if let Some(current) = self.claim_deadline {
+                assert!(now < current, "The claim deadline has passed: {}", current);
+            }
+            if let Some(deadline) = claim_deadline {
+                assert!(deadline > now, "The claim deadline should be in the future: {}", deadline);
+                if let Some(current) = self.claim_deadline {
+                    assert!(deadline >= current, "The claim deadline can only be extended: {}", current);
+                }
+            }
+            self.claim_deadline = claim_deadline;
+        }
+
+        /// Once the claim deadline has passed, moves the remaining WATER and won ICE to the treasury
+        /// and marks up to `limit` more tickets as expired - call repeatedly to mark all of them.
+        /// The swap pool, the swapped ICE and the ICE behind melt receipts are left to their holders:
+        /// swap tickets can still be shuffled and redeemed, receipts - exchanged for WATER.
+        pub fn sweep_unclaimed(&mut self, limit: u32) {
+            let deadline = self.claim_deadline.expect("Claim deadline is not set.");
+            assert!(Self::now() >= deadline, "Tickets can be redeemed until: {}", deadline);
+
+            let water = self.water.take_all();
+            self.treasury.put(water);
+            for round_id in 1..=self.round_count {
+                // the swap round's `water` counts the pooled ICE
+                if self.swap_round == Some(round_id) {
+                    continue;
+                }
+                let mut round = self.round_mut(round_id);
+                round.water = Decimal::zero();
+                round.open = false;
+            }
+            let ice = self.ice.take(self.ice.amount());
+            self.ice_treasury.put(ice);
+
+            let end = self.ticket_seq.min(self.expiry_cursor.saturating_add(limit));
+            for ticket_id in self.expiry_cursor..end {
+                let local_id = Self::ticket_local_id(ticket_id);
+                if self.ticket_manager.non_fungible_exists(&local_id) {
+                    let data: RandomIceTicket = self.ticket_manager.get_non_fungible_data(&local_id);
+                    if self.swap_round != Some(data.round) {
+                        self.ticket_manager.update_non_fungible_data(&local_id, "expired", true);
+                    }
+                } // ticket already burned - just ignore
+            }
+            self.expiry_cursor = end;
+        }
+
+        pub fn collect_unclaimed_ice(&mut self) -> Bucket {
+            return self.ice_treasury.take(self.ice_treasury.amount()).into();
+        }
+
+        /// Adds NFTs to be handed out by the lottery draws.
+        pub fn stock_prizes(&mut self, bucket: Bucket) {
+            assert!(self.rrc404.is_none(), "Prizes are only used in the lottery mode.");
+            self.prizes.put(bucket.as_non_fungible());
+        }
+
+        pub fn withdraw_prizes(&mut self, amount: Decimal) -> Bucket {
+            return self.prizes.take(amount).into();
+        }
+
+        fn assert_not_expired(&self) {
+            if let Some(deadline) = self.claim_deadline {
+                assert!(Self::now() < deadline, "Tickets have expired at: {}", deadline);
+            }
+        }
+
+        /// Closes the round: no more deposits or draws. Unused tickets can still be redeemed for WATER.
+        /// Requires the last requested draw to be completed.
+        pub fn finalize(&mut self, round_id: u32) -> CampaignSummary {
+            let mut round = self.round_mut(round_id);
+            assert!(round.summary.finalized_at.is_none(), "The round has already been finalized.");
+            assert_eq!(round.pending_draws, 0, "Please wait for the pending draws to complete.");
+
+            round.open = false;
+            round.summary.finalized_at = Some(Self::now());
+            if round.tickets_count > 0 {
+                round.consolation_share = round.consolation / Decimal::from(round.tickets_count);
+            }
+            let summary = round.summary.clone();
+            Runtime::emit_event(CampaignFinalizedEvent { round_id, summary: summary.clone() });
+            return summary;
+        }
+
+        /// Adds consolation tokens for the round, shared pro-rata by the tickets left unused at finalization.
+        pub fn fund_consolation(&mut self, round_id: u32, bucket: Bucket) {
+            assert!(self.round(round_id).summary.finalized_at.is_none(), "The round has been finalized.");
+            assert!(bucket.resource_address().is_fungible(), "Consolation should be a fungible token.");
+
+            let amount = bucket.amount();
+            match &mut self.consolation {
+                Some(vault) => {
+                    assert_eq!(vault.resource_address(), bucket.resource_address(),
+                               "Consolation is paid in {:?} for all rounds.", vault.resource_address()
+                    );
+                    vault.put(bucket);
+                }
+                None => self.consolation = Some(Vault::with_bucket(bucket)),
+            };
+            self.round_mut(round_id).consolation += amount;
+        }
+
+        /// Takes back the consolation tokens of a finalized round that no unused ticket can claim:
+        /// all of them once the round has no unused tickets left (or they have expired), the surplus otherwise.
+        pub fn withdraw_consolation(&mut self, round_id: u32) -> Bucket {
+            let expired = self.claim_deadline.is_some_and(|deadline| Self::now() >= deadline);
+            let amount = {
+                let mut round = self.round_mut(round_id);
+                assert!(round.summary.finalized_at.is_some(), "Please finalize the round first.");
+                let owed = if expired { Decimal::zero() } else { round.consolation_share * Decimal::from(round.tickets_count) };
+                let amount = (round.consolation - owed).max(Decimal::zero());
+                round.consolation -= amount;
+                amount
+            };
+            return self.consolation.as_mut().expect("The consolation has not been funded.").take(amount);
+        }
+
+        pub fn get_summary(&self, round_id: u32) -> CampaignSummary {
+            return self.round(round_id).summary.clone();
+        }
+
+        /// Returns the number of referred tickets, excluding the refunded ones.
+        pub fn get_referrals(&self, referrer: Referrer) -> u32 {
+            return self.referrals.get(&referrer).map(|count| *count).unwrap_or(0);
+        }
+
+        fn can_melt(&self) -> bool {
+            return Self::now() >= self.last_freeze_at + self.melt_cooldown;
+        }
+
+        fn melt_ice(&mut self, ice_ids: IndexSet<NonFungibleLocalId>) -> Bucket {
+            let ice_bucket = self.ice.take_non_fungibles(&ice_ids).into();
+            return self.rrc404().melt(ice_bucket);
+        }
+
+        /// Trades ICE for fresh random ICE: the ICE is melted back into WATER and a draw is requested for it.
+        /// Returns the reroll tickets - withdraw them after the draw to get the new ICE - and the XRD change.
+        /// `fee` should be exactly `reroll_fee` WATER per ICE, `draw_payment` should cover `draw_fee` XRD for the callback.
+        pub fn reroll(&mut self, ice_bucket: Bucket, fee: Bucket, mut draw_payment: Bucket) -> (Bucket, Bucket) {
+            assert_eq!(ice_bucket.resource_address(), self.ice.resource_address(), "Please provide ICE to reroll.");
+            assert!(self.reroll_fee.is_positive(), "Rerolls are disabled until the Owner sets the reroll fee.");
+            assert_eq!(draw_payment.resource_address(), XRD, "Please pay for the draw in XRD.");
+            assert!(draw_payment.amount() >= self.draw_fee, "The draw costs {} XRD.", self.draw_fee);
+            self.fee_vault.put(draw_payment.take(self.draw_fee));
+            self.assert_not_expired();
+            let (count, _) = Self::split_int_and_fraction(ice_bucket.amount());
+            let count: u32 = count.try_into().unwrap();
+            assert!(count > 0 && count < 100, "Please reroll from 1 to 99 ICE at once.");
+            let expected_fee = self.reroll_fee * Decimal::from(count);
+            assert_eq!(fee.amount(), expected_fee, "Reroll fee: {} WATER ({} per ICE)", expected_fee, self.reroll_fee);
+            self.treasury.put(fee);
+
+            let water = self.rrc404().melt(ice_bucket);
+            let round_id = self.reroll_round();
+            self.round_mut(round_id).water += water.amount();
+            self.water.put(water);
+
+            let tickets = self.mint_tickets(count, None, round_id, 0, None, None);
+            self.request_draw(round_id, count as u8, 0u8);
+            return (tickets, draw_payment);
+        }
+
+        pub fn set_reroll_fee(&mut self, reroll_fee: Decimal) {
+            assert!(!reroll_fee.is_negative(), "Reroll fee should not be negative.");
+            self.reroll_fee = reroll_fee;
+        }
+
+        fn reroll_round(&mut self) -> u32 {
+            if let Some(round_id) = self.reroll_round {
+                return round_id;
+            }
+            let round_id = self.add_closed_round();
+            self.reroll_round = Some(round_id);
+            return round_id;
+        }
+
+        /// Puts ICE into the swap pool for a blind trade. Returns swap tickets, one per ICE.
+        /// Once shuffled, each swap ticket can be withdrawn for a random ICE from the pool.
+        pub fn deposit_swap(&mut self, ice_bucket: Bucket) -> Bucket {
+            assert_eq!(ice_bucket.resource_address(), self.swap_ice.resource_address(), "Please provide ICE to swap.");
+            self.assert_not_expired();
+            let (count, _) = Self::split_int_and_fraction(ice_bucket.amount());
+            let count: u32 = count.try_into().unwrap();
+
+            let round_id = self.swap_round();
+            {
+                let mut round = self.round_mut(round_id);
+                round.water += Decimal::from(count);
+                round.summary.deposited += Decimal::from(count);
+            }
+            self.swap_ice.put(ice_bucket.as_non_fungible());
+            return self.mint_tickets(count, None, round_id, 0, None, None);
+        }
+
+        /// Takes swap tickets that are still waiting for the shuffle out of the pool and returns one pooled ICE per ticket -
+        /// not necessarily the deposited one. Works after the claim deadline too.
+        pub fn cancel_swap(&mut self, tickets: Bucket) -> Bucket {
+            assert_eq!(tickets.resource_address(), self.ticket_manager.address(), "Please provide swap tickets.");
+            let round_id = self.swap_round.expect("Nothing to swap yet.");
+            let local_ids = tickets.as_non_fungible().non_fungible_local_ids();
+            for local_id in &local_ids {
+                let id = Self::ticket_id(local_id);
+                let slot = self.tickets_id_to_idx.get(&id).map(|slot| *slot);
+                assert!(slot.is_some_and(|slot| slot.round_id == round_id), "Ticket {} is not waiting for the shuffle.", local_id);
+                self.remove_ticket(id);
+            }
+            let count = Decimal::from(local_ids.len() as u32);
+            {
+                let mut round = self.round_mut(round_id);
+                round.water -= count;
+                round.summary.redeemed += count;
+            }
             tickets.burn();
-            return (self.ice.take_non_fungibles(&ice_ids).into(), self.water.take(water_count));
+            return self.swap_ice.take(count).into();
+        }
+
+        /// Requests a draw shuffling up to `count` pooled ICE among the pending swap tickets.
+        pub fn shuffle_swaps(&mut self, count: u8) -> u32 {
+            let round_id = self.swap_round.expect("Nothing to swap yet.");
+            return self.request_draw(round_id, count, 0u8);
+        }
+
+        fn swap_round(&mut self) -> u32 {
+            if let Some(round_id) = self.swap_round {
+                return round_id;
+            }
+            let round_id = self.add_closed_round();
+            self.swap_round = Some(round_id);
+            return round_id;
+        }
+
+        /// Adds a round that doesn't accept deposits - for the reroll and swap tickets.
+        fn add_closed_round(&mut self) -> u32 {
+            self.round_count += 1;
+            let mut round = Self::new_round();
+            round.open = false;
+            self.rounds.insert(self.round_count, round);
+            return self.round_count;
+        }
+
+        /// Sets (or removes) the protocol fee and the stage at which it is charged.
+        pub fn set_protocol_fee(&mut self, fee: Option<ProtocolFee>, stage: FeeStage) {
+            let (per_ticket, max) = match &fee {
+                Some(ProtocolFee::Percent(percent)) => (*percent, Decimal::ONE),
+                Some(ProtocolFee::Flat(amount)) => (*amount, self.ticket_price),
+                None => (Decimal::zero(), Decimal::ONE),
+            };
+            assert!(!per_ticket.is_negative() && per_ticket < max,
+                    "The fee should be in range [0, {}) per ticket. {}", max, per_ticket
+            );
+            self.protocol_fee = fee;
+            self.fee_stage = stage;
+        }
+
+        pub fn collect_fees(&mut self) -> Bucket {
+            return self.treasury.take_all();
         }
 
 
         pub fn mint(&mut self, mint_count: u8, melt_count: u8) -> u32 {
+            return self.request_draw(self.current_round, mint_count, melt_count);
+        }
+
+        pub fn mint_round(&mut self, round_id: u32, mint_count: u8, melt_count: u8) -> u32 {
+            return self.request_draw(round_id, mint_count, melt_count);
+        }
+
+        /// Lets anyone request a draw once enough tickets are pending and the cooldown has passed.
+        /// The caller is rewarded with a bounty from the `bounty` vault (if it's not empty).
+        pub fn trigger_mint(&mut self) -> Bucket {
+            let config = self.keeper_config.clone().expect("Keeper mints are disabled.");
+            let tickets_count = self.round(self.current_round).tickets_count;
+            assert!(tickets_count > config.threshold,
+                    "Not enough pending tickets: {} (threshold: {})", tickets_count, config.threshold
+            );
+            let next_draw_at = self.last_draw_at + config.cooldown;
+            assert!(Self::now() >= next_draw_at, "Next draw is allowed at: {}", next_draw_at);
+
+            self.request_draw(self.current_round, config.batch_size, 0u8);
+
+            let bounty = self.bounty.amount().min(config.bounty);
+            return self.bounty.take(bounty);
+        }
+
+        pub fn set_keeper_config(&mut self, threshold: u16, cooldown: i64, batch_size: u8, bounty: Decimal) {
+            assert!(batch_size > 0 && batch_size < 100, "Batch size should be in range [1, 99].");
+            assert!(cooldown >= 0 && !bounty.is_negative(), "Cooldown and bounty should not be negative.");
+            self.keeper_config = Some(KeeperConfig { threshold, cooldown, batch_size, bounty });
+        }
+
+        pub fn fund_bounty(&mut self, bucket: Bucket) {
+            self.bounty.put(bucket);
+        }
+
+        pub fn withdraw_bounty(&mut self, amount: Decimal) -> Bucket {
+            return self.bounty.take(amount);
+        }
+
+        /// Configures the callback fee: `expected_fee` is passed to .Random,
+        /// `draw_fee` is the amount of XRD locked from the fee vault in each callback.
+        pub fn set_draw_fee(&mut self, expected_fee: u8, draw_fee: Decimal) {
+            assert!(!draw_fee.is_negative(), "Draw fee should not be negative.");
+            self.expected_fee = expected_fee;
+            self.draw_fee = draw_fee;
+        }
+
+        pub fn fund_fees(&mut self, bucket: Bucket) {
+            self.fee_vault.put(bucket);
+        }
+
+        pub fn withdraw_fees(&mut self, amount: Decimal) -> Bucket {
+            return self.fee_vault.take(amount);
+        }
+
+        fn request_draw(&mut self, round_id: u32, mint_count: u8, melt_count: u8) -> u32 {
+            assert!(mint_count < 100 && melt_count < 100, "Please mint/melt less than 100 NFTs per draw.");
+            assert!(melt_count == 0 || self.rrc404.is_some(), "Melting is not available in the lottery mode.");
+            {
+                let mut round = self.round_mut(round_id);
+                assert!(round.summary.finalized_at.is_none(), "The round has been finalized.");
+                round.pending_draws += 1;
+            }
+            // only the draws of the current round (by the Owner or keepers) count for the keeper cooldown
+            if round_id == self.current_round {
+                self.last_draw_at = Self::now();
+            }
+
             let address = Runtime::global_component().address();
             let method_name = "do_mint".into();
-            let on_error = "".into();
-            // `key` encodes two variables: (key = m * 100 + n)
+            let on_error = "abort_mint".into();
+            // `key` encodes three variables: (key = r * 10000 + m * 100 + n)
             // 1) n - the number of NFTs to mint
             // 2) m - the number just minted NFTs to add to the "melt pool".
+            // 3) r - the round to draw.
             // Using such a compound key allows to avoid maintaining one more KVS.
-            let key: u32 = (melt_count as u32) * 100u32 + mint_count as u32;
-            return RNG.request_random(address, method_name, on_error, key, None, 60u8);
+            let key: u32 = round_id * 10000u32 + (melt_count as u32) * 100u32 + mint_count as u32;
+            return RNG.request_random(address, method_name, on_error, key, None, self.expected_fee);
         }
 
         pub fn do_mint(&mut self, key: u32, random_seed: Vec<u8>) {
             debug!("LOG:IceRandomizer::do_mint({:?}, {:?})", key, random_seed);
 
             let mint_count = key % 100;
-            let mut melt_count = key / 100;
+            let mut melt_count = key / 100 % 100;
+            let round_id = key / 10000;
+            let swap = self.swap_round == Some(round_id);
+            let available = match (swap, self.rrc404) {
+                (true, _) => Self::whole(self.swap_ice.amount()).min(mint_count),
+                (false, Some(_)) => mint_count,
+                (false, None) => Self::whole(self.prizes.amount()).min(mint_count),
+            };
+            // the swap round's `water` counts the pooled ICE
+            let price = if swap { Decimal::ONE } else { self.ticket_price };
+            let (count, water) = {
+                let mut round = self.round_mut(round_id);
+                round.pending_draws = round.pending_draws.saturating_sub(1);
+                let count = Self::whole(round.water / price).min(available);
+                let water = price * Decimal::from(count);
+                round.water -= water;
+                (count, water)
+            };
 
-            let amount = self.water.amount().min(Decimal::from(mint_count));
-            let bucket = self.water.take(amount);
-            let (minted_ice_fungible, empty_bucket) = RRC404.freeze(bucket);
+            let fee = self.draw_fee.min(self.fee_vault.amount());
+            if fee.is_positive() {
+                self.fee_vault.as_fungible().lock_fee(fee);
+                self.fees_locked += fee;
+            }
 
-            let minted_ice = minted_ice_fungible.as_non_fungible();
+            let minted_ice = if swap {
+                self.swap_ice.take(water)
+            } else {
+                let bucket = self.water.take(water);
+                match self.rrc404 {
+                    Some(_) => {
+                        let (minted_ice_fungible, remainder) = self.rrc404().freeze(bucket);
+                        assert!(remainder.is_empty(),
+                                "RRC404 left {} WATER unfrozen - check the units per NFT: {}", remainder.amount(), price
+                        );
+                        remainder.drop_empty();
+                        self.last_freeze_at = Self::now();
+                        minted_ice_fungible.as_non_fungible()
+                    }
+                    None => {
+                        self.treasury.put(bucket);
+                        self.prizes.take(Decimal::from(count))
+                    }
+                }
+            };
             let nft_ids = minted_ice.non_fungible_local_ids();
+            let minted = nft_ids.len() as u32;
 
-            self.ice.put(minted_ice);
+            if swap {
+                self.swapped_ice.put(minted_ice);
+            } else {
+                self.ice.put(minted_ice);
+            }
 
+            let draw_id = self.draw_count + 1;
             let mut random: Random = Random::new(&random_seed);
 
+            let rarity = self.rarity.clone();
+            let mut tiers: Vec<TierCount> = Vec::new();
+            // depositors that have won a top-tier ICE in this draw
+            let mut top_winners: IndexSet<Depositor> = IndexSet::new();
+            let guaranteed: Vec<u32> = std::mem::take(&mut self.round_mut(round_id).guaranteed);
+            let guaranteed: Vec<u32> = guaranteed.into_iter()
+                .filter(|ticket_id| self.tickets_id_to_idx.get(ticket_id).is_some())
+                .collect();
+            let mut guaranteed_served = 0usize;
             for ice_id in nft_ids {
-                let winner_idx = random.roll::<u16>(self.tickets_count);
-                let winner = *self.tickets_by_idx.get(&winner_idx).unwrap();
+                let guaranteed_win = guaranteed_served < guaranteed.len();
+                let winner = match &rarity {
+                    _ if guaranteed_served < guaranteed.len() => {
+                        let winner = guaranteed[guaranteed_served];
+                        guaranteed_served += 1;
+                        if let Some(config) = &rarity {
+                            let tier = self.ice_tier(config.field_index, &ice_id);
+                            let depositor = self.ticket_depositor(winner);
+                            if config.top_tiers.contains(&tier) {
+                                top_winners.insert(depositor.clone());
+                            }
+                            Self::count_tier(&mut tiers, depositor, tier);
+                        }
+                        winner
+                    }
+                    None => {
+                        let round = self.round(round_id);
+                        let winner_idx = random.roll::<u16>(round.tickets_count);
+                        let winner = *round.tickets_by_idx.get(&winner_idx).unwrap();
+                        winner
+                    }
+                    Some(config) => {
+                        let tier = self.ice_tier(config.field_index, &ice_id);
+                        let top = config.top_tiers.contains(&tier);
+                        let (winner, depositor) = self.roll_rare_winner(round_id, &mut random, top, &top_winners);
+                        if top {
+                            top_winners.insert(depositor.clone());
+                        }
+                        Self::count_tier(&mut tiers, depositor, tier);
+                        winner
+                    }
+                };
                 self.remove_ticket(winner);
-                let local_id = NonFungibleLocalId::integer(winner as u64);
+                self.reward_referrer(winner);
+                let local_id = Self::ticket_local_id(winner);
                 self.ticket_manager.update_non_fungible_data(
                     &local_id,
                     "result",
                     Some(ice_id),
                 );
-                if melt_count > 0 {
+                self.ticket_manager.update_non_fungible_data(&local_id, "draw", Some(draw_id));
+                self.update_ticket_status(&local_id, TicketStatus::Won);
+                // the guaranteed ICE is never melted back
+                if melt_count > 0 && !guaranteed_win {
                     self.melt_list.push(winner);
                     melt_count -= 1;
                 }
             }
 
-            empty_bucket.drop_empty();
+            self.draw_count += 1;
+            {
+                let mut round = self.round_mut(round_id);
+                round.guaranteed = guaranteed[guaranteed_served..].to_vec();
+                round.summary.minted += minted;
+                round.draws.push(self.draw_count);
+            }
+            self.draws.insert(self.draw_count, DrawRecord { round_id, minted, fee, drawn_at: Self::now(), tiers });
+            Runtime::emit_event(DrawEvent {
+                draw_id: self.draw_count,
+                round_id,
+                minted,
+                fee,
+                fee_balance: self.fee_vault.amount(),
+            });
+        }
+
+        /// Called by .Random when `do_mint()` fails: releases the draw, so that the round can be finalized.
+        /// The tickets stay in the pool for the next draws.
+        pub fn abort_mint(&mut self, key: u32) {
+            debug!("LOG:IceRandomizer::abort_mint({:?})", key);
+            let round_id = key / 10000;
+            let mut round = self.round_mut(round_id);
+            round.pending_draws = round.pending_draws.saturating_sub(1);
+        }
+
+        /// Rolls a ticket from the round's pool. For a top-tier ICE, the tickets of the depositors in `top_winners`
+        /// are re-rolled, up to `RARITY_PROBES` rolls in total - if all of them hit such tickets, the first rolled one wins.
+        /// So the winner is uniform among the other tickets, except with the probability of (b/n)^RARITY_PROBES
+        /// (b - the tickets of `top_winners`, n - the pool size), when it's uniform among all the tickets.
+        /// Returns the ticket ID and its depositor.
+        fn roll_rare_winner(&self, round_id: u32, random: &mut Random, top: bool, top_winners: &IndexSet<Depositor>)
+            -> (u32, Depositor) {
+            let round = self.round(round_id);
+            let probes = if top { RARITY_PROBES } else { 1 };
+
+            let mut rolled: Option<(u32, Depositor)> = None;
+            for _ in 0..probes {
+                let idx = random.roll::<u16>(round.tickets_count);
+                let ticket_id = *round.tickets_by_idx.get(&idx).unwrap();
+                let depositor = self.ticket_depositor(ticket_id);
+                if !top || !top_winners.contains(&depositor) {
+                    return (ticket_id, depositor);
+                }
+                rolled.get_or_insert((ticket_id, depositor));
+            }
+            return rolled.unwrap();
+        }
+
+        /// The identity badge, the account or the deposit of the ticket - whichever is known, in that order.
+        fn ticket_depositor(&self, ticket_id: u32) -> Depositor {
+            let data = self.ticket_data(&Self::ticket_local_id(ticket_id));
+            return match (data.identity, data.depositor) {
+                (Some(identity), _) => Depositor::Identity(identity),
+                (None, Some(account)) => Depositor::Account(account),
+                (None, None) => Depositor::Deposit(data.deposit),
+            };
+        }
+
+        fn count_tier(tiers: &mut Vec<TierCount>, depositor: Depositor, tier: String) {
+            match tiers.iter_mut().find(|t| t.depositor == depositor && t.tier == tier) {
+                Some(tier_count) => tier_count.count += 1,
+                None => tiers.push(TierCount { depositor, tier, count: 1 }),
+            };
+        }
+
+        /// Reads the `field_index`-th field of the ICE data as a tier.
+        fn ice_tier(&self, field_index: u8, ice_id: &NonFungibleLocalId) -> String {
+            let output = ScryptoVmV1Api::object_call(
+                self.ice.resource_address().as_node_id(),
+                NON_FUNGIBLE_RESOURCE_MANAGER_GET_NON_FUNGIBLE_IDENT,
+                scrypto_encode(&NonFungibleResourceManagerGetNonFungibleInput { id: ice_id.clone() }).unwrap(),
+            );
+            let data: ScryptoValue = scrypto_decode(&output).unwrap();
+            let field = match data {
+                ScryptoValue::Tuple { fields } => fields.into_iter().nth(field_index as usize),
+                _ => None,
+            }.unwrap_or_else(|| panic!("ICE data has no field #{}", field_index));
+            return match field {
+                ScryptoValue::String { value } => value,
+                ScryptoValue::U8 { value } => value.to_string(),
+                ScryptoValue::U16 { value } => value.to_string(),
+                ScryptoValue::U32 { value } => value.to_string(),
+                ScryptoValue::U64 { value } => value.to_string(),
+                ScryptoValue::Enum { discriminator, .. } => discriminator.to_string(),
+                _ => panic!("Unsupported rarity field #{} of ICE {}", field_index, ice_id),
+            };
+        }
+
+        pub fn set_rarity_config(&mut self, config: Option<RarityConfig>) {
+            self.rarity = config;
+        }
+
+        /// Sends the won ICE to the accounts recorded on the tickets and marks the tickets as claimed.
+        /// Tickets without a depositor or without a win are skipped.
+        /// If the account rejects the deposit, the ICE stays claimable via `withdraw()`.
+        /// Not available after the claim deadline - the unclaimed ICE is swept.
+        pub fn distribute(&mut self, ticket_ids: Vec<NonFungibleLocalId>) {
+            self.assert_not_expired();
+            for local_id in ticket_ids {
+                let data = self.ticket_data(&local_id);
+                if data.claimed {
+                    continue;
+                }
+                match (data.result, data.depositor) {
+                    (Some(ice_id), Some(address)) => {
+                        let ice: Bucket = self.ice.take_non_fungible(&ice_id).into();
+                        let account: Global<Account> = Global::from(address);
+                        match account.try_deposit_or_refund(ice, None) {
+                            Some(refund) => {
+                                self.ice.put(refund.as_non_fungible());
+                            }
+                            None => {
+                                self.ticket_manager.update_non_fungible_data(&local_id, "claimed", true);
+                                self.update_ticket_status(&local_id, TicketStatus::Redeemed);
+                            }
+                        };
+                    }
+                    _ => {}
+                };
+            }
         }
 
         pub fn melt(&mut self) {
@@ -280,19 +1929,35 @@ mod ice {
 
             let mut ice_to_melt: IndexSet<NonFungibleLocalId> = IndexSet::new();
             for ticket_id in self.melt_list.clone() {
-                let local_id = NonFungibleLocalId::integer(ticket_id as u64);
+                let local_id = Self::ticket_local_id(ticket_id);
 
                 if self.ticket_manager.non_fungible_exists(&local_id) {
                     let data: RandomIceTicket = self.ticket_manager.get_non_fungible_data(&local_id);
+                    if data.claimed {
+                        continue; // ICE already distributed
+                    }
+                    if self.round(data.round).summary.finalized_at.is_some() {
+                        continue; // no more draws in the round - the ticket keeps the ICE
+                    }
+                    let tracked = Self::is_tracked(&data);
                     match data.result {
                         Some(ice_id) => {
                             ice_to_melt.insert(ice_id);
-                            self.add_ticket(ticket_id);
+                            self.add_ticket(data.round, ticket_id, tracked);
+                            let price = self.ticket_price;
+                            {
+                                // each ICE melts back into the price of a ticket
+                                let mut round = self.round_mut(data.round);
+                                round.water += price;
+                                round.summary.melted += 1;
+                            }
                             self.ticket_manager.update_non_fungible_data(
                                 &local_id,
                                 "result",
                                 None::<NonFungibleLocalId>,
                             );
+                            self.ticket_manager.update_non_fungible_data(&local_id, "draw", None::<u32>);
+                            self.update_ticket_status(&local_id, TicketStatus::MeltedBack);
                         }
                         None => {
                             panic!("Not possible");
@@ -302,32 +1967,100 @@ mod ice {
 
             }
 
-            let ice_bucket = self.ice.take_non_fungibles(&ice_to_melt).into();
-            let water_bucket = RRC404.melt(ice_bucket);
-            self.water.put(water_bucket);
+            if !ice_to_melt.is_empty() {
+                let water_bucket = self.melt_ice(ice_to_melt);
+                self.water.put(water_bucket);
+            }
             self.melt_list.clear();
         }
 
 
-        fn add_ticket(&mut self, ticket_id: u32) {
-            let index = self.tickets_count;
-            self.tickets_by_idx.insert(index, ticket_id);
-            self.tickets_id_to_idx.insert(ticket_id, index);
-            self.tickets_count += 1;
+        /// WATER charged per ticket at the given stage.
+        fn ticket_fee(&self, stage: FeeStage) -> Decimal {
+            if self.fee_stage != stage {
+                return Decimal::zero();
+            }
+            return match &self.protocol_fee {
+                Some(ProtocolFee::Percent(percent)) => *percent * self.ticket_price,
+                Some(ProtocolFee::Flat(amount)) => *amount,
+                None => Decimal::zero(),
+            };
+        }
+
+        fn rrc404(&self) -> Global<Rrc404NFT> {
+            let address = self.rrc404.expect("Melting is not available in the lottery mode.");
+            return Global::from(address);
+        }
+
+        fn now() -> i64 {
+            return Clock::current_time_rounded_to_minutes().seconds_since_unix_epoch;
+        }
+
+        /// Tickets have integer IDs in range [1, ticket_seq).
+        fn ticket_id(local_id: &NonFungibleLocalId) -> u32 {
+            return match local_id {
+                NonFungibleLocalId::Integer(int_id) => u32::try_from(int_id.value())
+                    .unwrap_or_else(|_| panic!("Unknown ticket: {}", local_id)),
+                _ => panic!("Invalid ticket ID: {}. Ticket IDs are integers.", local_id),
+            };
+        }
+
+        fn ticket_name(ticket_id: u32, status: TicketStatus) -> String {
+            return format!("IRAND #{}: {}", ticket_id, status.label());
+        }
+
+        fn update_ticket_status(&self, local_id: &NonFungibleLocalId, status: TicketStatus) {
+            let ticket_id = Self::ticket_id(local_id);
+            self.ticket_manager.update_non_fungible_data(local_id, "status", status);
+            self.ticket_manager.update_non_fungible_data(local_id, "name", Self::ticket_name(ticket_id, status));
+            self.ticket_manager.update_non_fungible_data(local_id, "key_image_url", status.image_url());
+        }
+
+        fn ticket_local_id(ticket_id: u32) -> NonFungibleLocalId {
+            return NonFungibleLocalId::integer(ticket_id as u64);
+        }
+
+        fn ticket_data(&self, local_id: &NonFungibleLocalId) -> RandomIceTicket {
+            let ticket_id = Self::ticket_id(local_id);
+            assert!(self.ticket_manager.non_fungible_exists(local_id), "Unknown ticket: #{}#", ticket_id);
+            return self.ticket_manager.get_non_fungible_data(local_id);
+        }
+
+        fn round(&self, round_id: u32) -> KeyValueEntryRef<'_, Round> {
+            return self.rounds.get(&round_id)
+                .unwrap_or_else(|| panic!("Unknown round: {}", round_id));
+        }
+
+        fn round_mut(&mut self, round_id: u32) -> KeyValueEntryRefMut<'_, Round> {
+            return self.rounds.get_mut(&round_id)
+                .unwrap_or_else(|| panic!("Unknown round: {}", round_id));
+        }
+
+        fn add_ticket(&mut self, round_id: u32, ticket_id: u32, tracked: bool) {
+            let mut round = self.rounds.get_mut(&round_id).unwrap();
+            let idx = round.tickets_count;
+            round.tickets_by_idx.insert(idx, ticket_id);
+            round.tickets_count += 1;
+            self.tickets_id_to_idx.insert(ticket_id, PoolSlot { round_id, idx, tracked });
         }
 
-        fn remove_ticket(&mut self, id: u32) {
-            let idx = self.tickets_id_to_idx.remove(&id).unwrap();
-            self.tickets_by_idx.remove(&idx);
+        /// Removes the ticket from its round's pool. Returns the round ID.
+        fn remove_ticket(&mut self, id: u32) -> u32 {
+            let slot = self.tickets_id_to_idx.remove(&id)
+                .unwrap_or_else(|| panic!("Ticket #{}# is not pending the draw.", id));
+            let mut round = self.rounds.get_mut(&slot.round_id).unwrap();
+            round.tickets_by_idx.remove(&slot.idx);
 
             // if there is a gap - move the last ticket there
-            let last_idx = self.tickets_count - 1;
-            if idx != last_idx {
-                let last = self.tickets_by_idx.remove(&last_idx).unwrap();
-                self.tickets_id_to_idx.insert(last, idx);
-                self.tickets_by_idx.insert(idx, last);
+            let last_idx = round.tickets_count - 1;
+            if slot.idx != last_idx {
+                let last = round.tickets_by_idx.remove(&last_idx).unwrap();
+                let last_slot = *self.tickets_id_to_idx.get(&last).unwrap();
+                self.tickets_id_to_idx.insert(last, PoolSlot { idx: slot.idx, ..last_slot });
+                round.tickets_by_idx.insert(slot.idx, last);
             }
-            self.tickets_count -= 1;
+            round.tickets_count -= 1;
+            return slot.round_id;
         }
     }
 }
\ No newline at end of file
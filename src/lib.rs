@@ -1 +1,2 @@
-mod ice_randomizer;
\ No newline at end of file
+mod ice_randomizer;
+mod randomizer_factory;
@@ -0,0 +1,89 @@
+use scrypto::prelude::*;
+
+use crate::ice_randomizer::ice::*;
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct RandomizerInfo {
+    pub component: ComponentAddress,
+    /// The RRC404 component with the `freeze`/`melt` interface. `None` - a lottery.
+    pub rrc404: Option<ComponentAddress>,
+    pub water: ResourceAddress,
+    pub ice: ResourceAddress,
+    /// The badge returned to the randomizer owner - it also sets the branding metadata.
+    pub owner_badge: ResourceAddress,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct RandomizerCreatedEvent {
+    pub id: u32,
+    pub info: RandomizerInfo,
+}
+
+#[blueprint]
+#[events(RandomizerCreatedEvent)]
+#[types(u32, RandomizerInfo)]
+mod factory {
+    struct RandomizerFactory {
+        /// All the randomizers created, the key is ordinal - in range [1, randomizers_count].
+        randomizers: KeyValueStore<u32, RandomizerInfo>,
+        randomizers_count: u32,
+    }
+
+    impl RandomizerFactory {
+        pub fn instantiate() -> Global<RandomizerFactory> {
+            debug!("LOG:RandomizerFactory::instantiate()\n");
+            return Self {
+                randomizers: KeyValueStore::new_with_registered_type(),
+                randomizers_count: 0,
+            }
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .globalize();
+        }
+
+        /// Creates a randomizer for the given RRC404 collection, freezing `units_per_nft` WATER into one NFT.
+        /// Returns the randomizer and its owner badge. The owner brands the randomizer (and its tickets)
+        /// by updating the "name", "description" and "icon_url" metadata.
+        pub fn create_randomizer(&mut self, rrc404: ComponentAddress, water: ResourceAddress, ice: ResourceAddress,
+                                 units_per_nft: Decimal) -> (Global<IceRandomizer>, Bucket) {
+            debug!("LOG:RandomizerFactory::create_randomizer() rrc404: {:?}\n", rrc404);
+            let (randomizer, owner_badge) = Blueprint::<IceRandomizer>::instantiate_with(rrc404, water, ice, units_per_nft);
+            self.register(randomizer, Some(rrc404), water, ice, &owner_badge);
+            return (randomizer, owner_badge);
+        }
+
+        /// Creates a lottery, handing out the `prizes` NFTs for tickets bought with `payment`, `price` tokens each.
+        pub fn create_lottery(&mut self, payment: ResourceAddress, prizes: ResourceAddress, price: Decimal)
+            -> (Global<IceRandomizer>, Bucket) {
+            debug!("LOG:RandomizerFactory::create_lottery() prizes: {:?}\n", prizes);
+            let (randomizer, owner_badge) = Blueprint::<IceRandomizer>::instantiate_lottery(payment, prizes, price);
+            self.register(randomizer, None, payment, prizes, &owner_badge);
+            return (randomizer, owner_badge);
+        }
+
+        fn register(&mut self, randomizer: Global<IceRandomizer>, rrc404: Option<ComponentAddress>,
+                    water: ResourceAddress, ice: ResourceAddress, owner_badge: &Bucket) {
+            let info = RandomizerInfo {
+                component: randomizer.address(),
+                rrc404,
+                water,
+                ice,
+                owner_badge: owner_badge.resource_address(),
+            };
+            self.randomizers_count += 1;
+            let id = self.randomizers_count;
+            self.randomizers.insert(id, info.clone());
+            Runtime::emit_event(RandomizerCreatedEvent { id, info });
+        }
+
+        pub fn get_randomizer(&self, id: u32) -> RandomizerInfo {
+            return self.randomizers.get(&id)
+                .unwrap_or_else(|| panic!("Unknown randomizer: {}", id))
+                .clone();
+        }
+
+        pub fn get_randomizers_count(&self) -> u32 {
+            return self.randomizers_count;
+        }
+    }
+}
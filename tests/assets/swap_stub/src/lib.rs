@@ -0,0 +1,33 @@
+use scrypto::prelude::*;
+
+/// A stand-in swap provider with a fixed rate, for tests and local setups.
+#[blueprint]
+mod swap_stub {
+    struct SwapStub {
+        /// WATER paid out by `swap()`.
+        water: Vault,
+        /// The tokens taken by `swap()`.
+        input: Vault,
+        /// WATER per 1 input token.
+        rate: Decimal,
+    }
+
+    impl SwapStub {
+        pub fn instantiate(water: Bucket, input_resource: ResourceAddress, rate: Decimal) -> Global<SwapStub> {
+            return Self {
+                water: Vault::with_bucket(water),
+                input: Vault::new(input_resource),
+                rate,
+            }
+                .instantiate()
+                .prepare_to_globalize(OwnerRole::None)
+                .globalize();
+        }
+
+        pub fn swap(&mut self, bucket: Bucket) -> Bucket {
+            let water = self.water.take(bucket.amount() * self.rate);
+            self.input.put(bucket);
+            return water;
+        }
+    }
+}
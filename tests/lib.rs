@@ -3,6 +3,7 @@ use std::ops::Add;
 
 use dot_random_test_utils::{deploy_random_component, RandomTestEnv};
 use dot_random_test_utils::cargo::get_repo_sub_dir;
+use radix_engine::transaction::TransactionReceipt;
 use radix_engine::vm::NoExtension;
 use scrypto::this_package;
 use scrypto_test::prelude::InMemorySubstateDatabase;
@@ -10,7 +11,7 @@ use scrypto_unit::*;
 use transaction::prelude::*;
 
 use consts::{RRC404_COMPONENT, RRC404_ICE, RRC404_PACKAGE, RRC404_WATER};
-use structs::{Account, DeployedEnv, IceRandomizerState, TestEnv};
+use structs::{Account, AllowlistConfig, CampaignSummary, DeployedEnv, DepositGate, DepositLimits, FeeStage, IceRandomizerState, ParticipantBadge, PaymentToken, ProtocolFee, RandomIceTicket, RarityConfig, Referrer, TestEnv, TicketStatus, TieredNft};
 
 mod consts;
 mod structs;
@@ -432,6 +433,1707 @@ fn test_can_withdraw_during_mint() {
     assert_eq!(dec!(0), balance_water);
 }
 
+#[test]
+fn test_trigger_mint() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &amounts);
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+
+    // 1. Owner enables keeper mints: > 100 pending tickets, 1hr cooldown, 40 NFTs per draw, 5 XRD bounty
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_keeper_config",
+                manifest_args!(100u16, 3600i64, 40u8, dec!(5)),
+            )
+            .withdraw_from_account(env.owner.address, XRD, dec!(50))
+            .take_all_from_worktop(XRD, "bounty")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "fund_bounty",
+                    manifest_args!(lookup.bucket("bounty")),
+                )
+            })
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act
+    // 2. Anyone triggers the mint and gets the bounty
+    let receipt = trigger_mint(&mut test_runner, test, env.users[0]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Assert minted 40 ICE and paid the bounty
+    let balance_ice = test_runner.get_component_balance(test.ice_randomizer, RRC404_ICE);
+    assert_eq!(dec!(40), balance_ice);
+    let balance_xrd = test_runner.get_component_balance(test.ice_randomizer, XRD);
+    assert_eq!(dec!(45), balance_xrd);
+
+    // 3. The next trigger is rejected due to the cooldown
+    let receipt = trigger_mint(&mut test_runner, test, env.users[1]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Next draw is allowed at"));
+}
+
+#[test]
+fn test_draw_fees() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &amounts);
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+
+    // 1. Owner tops up the fee vault and sets the fee to 2 XRD per draw
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_draw_fee",
+                manifest_args!(60u8, dec!(2)),
+            )
+            .withdraw_from_account(env.owner.address, XRD, dec!(10))
+            .take_all_from_worktop(XRD, "fees")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "fund_fees",
+                    manifest_args!(lookup.bucket("fees")),
+                )
+            })
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act
+    // 2. Owner mints in 2 batches
+    for index in 0u32..2 {
+        let receipt = test_runner.execute_manifest(
+            ManifestBuilder::new()
+                .lock_fee_from_faucet()
+                .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+                .call_method(
+                    test.ice_randomizer,
+                    "mint",
+                    manifest_args!(20u8, 0u8),
+                )
+                .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+        receipt.expect_commit_success();
+        random_env.execute_next(&mut test_runner, index + 1);
+    }
+
+    // Assert 2 draws locked 2 XRD each, the callbacks were paid from the vault within the locked amount
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    assert_eq!(2, state.draw_count);
+    assert_eq!(dec!(4), state.fees_locked);
+    let spent = dec!(10) - test_runner.get_component_balance(test.ice_randomizer, XRD);
+    assert!(spent.is_positive() && spent <= state.fees_locked, "XRD spent: {}", spent);
+}
+
+#[test]
+fn test_protocol_fee_conserves_water() {
+    for stage in [FeeStage::Deposit, FeeStage::Withdraw] {
+        // Arrange
+        let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+        let env = TestEnv::init(&mut test_runner);
+        let (mut random_env, test) = env.deploy(&mut test_runner);
+
+        // 10% on top of the ticket price at deposit, or 10% of the refund at withdrawal
+        let receipt = test_runner.execute_manifest(
+            ManifestBuilder::new()
+                .lock_fee_from_faucet()
+                .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+                .call_method(
+                    test.ice_randomizer,
+                    "set_protocol_fee",
+                    manifest_args!(Some(ProtocolFee::Percent(dec!("0.1"))), stage),
+                )
+                .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+        receipt.expect_commit_success();
+
+        let price = match stage {
+            FeeStage::Deposit => dec!("1.1"),
+            FeeStage::Withdraw => dec!(1),
+        };
+        let amounts = AMOUNTS.map(|amount| amount * price);
+        allocate_tokens(&mut test_runner, test, &amounts);
+        let total = sum(&amounts);
+
+        // Act
+        for index in 0..amounts.len() {
+            deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+        }
+        let receipt = test_runner.execute_manifest(
+            ManifestBuilder::new()
+                .lock_fee_from_faucet()
+                .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+                .call_method(
+                    test.ice_randomizer,
+                    "mint",
+                    manifest_args!(40u8, 0u8),
+                )
+                .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+        receipt.expect_commit_success();
+        random_env.execute_next(&mut test_runner, 1);
+
+        for index in 0..amounts.len() {
+            withdraw_ice(&mut test_runner, test, env.users[index], AMOUNTS[index]);
+        }
+
+        let owner_water = test_runner.get_component_balance(env.owner.address, RRC404_WATER);
+        let receipt = test_runner.execute_manifest(
+            ManifestBuilder::new()
+                .lock_fee_from_faucet()
+                .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+                .call_method(
+                    test.ice_randomizer,
+                    "collect_fees",
+                    manifest_args!(),
+                )
+                .deposit_batch(env.owner.address)
+                .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+        receipt.expect_commit_success();
+        let fees = test_runner.get_component_balance(env.owner.address, RRC404_WATER) - owner_water;
+
+        // Assert WATER is conserved: users' WATER + ICE + collected fees == deposited WATER
+        let expected_fees = match stage {
+            FeeStage::Deposit => dec!(14), // 140 tickets
+            FeeStage::Withdraw => dec!(10), // 100 unused tickets
+        };
+        assert_eq!(expected_fees, fees);
+        let mut users_total = Decimal::zero();
+        for user in env.users {
+            users_total += test_runner.get_component_balance(user.address, RRC404_WATER);
+            users_total += test_runner.get_component_balance(user.address, RRC404_ICE);
+        }
+        assert_eq!(total, users_total + fees);
+        let balance_water = test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER);
+        assert_eq!(dec!(0), balance_water);
+    }
+}
+
+#[test]
+fn test_deposit_for() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+
+    let recipients: Vec<(ComponentAddress, u32)> = env.users.iter().zip(AMOUNTS)
+        .map(|(user, amount)| (user.address, amount.to_string().parse::<u32>().unwrap()))
+        .collect();
+
+    // Act
+    // 1. The owner (e.g. a DAO) buys the tickets for all the users
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(env.owner.address, RRC404_WATER, sum(&AMOUNTS))
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_for",
+                    manifest_args!(lookup.bucket("bucket1"), recipients),
+                )
+            })
+            .deposit_batch(env.owner.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Assert every user got the tickets
+    for index in 0..AMOUNTS.len() {
+        let balance_tickets = test_runner.get_component_balance(env.users[index].address, test.ticket_address);
+        assert_eq!(AMOUNTS[index], balance_tickets);
+    }
+    let balance_tickets = test_runner.get_component_balance(env.owner.address, test.ticket_address);
+    assert_eq!(dec!(0), balance_tickets);
+    let balance_water = test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER);
+    assert_eq!(sum(&AMOUNTS), balance_water);
+
+    // 2. Recipient counts must match the deposit
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(env.owner.address, RRC404_WATER, dec!(10))
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_for",
+                    manifest_args!(lookup.bucket("bucket1"), vec![(env.users[0].address, 9u32)]),
+                )
+            })
+            .deposit_batch(env.owner.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Recipients should receive exactly 10 tickets"));
+}
+
+#[test]
+fn test_distribute() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    // 1. Users' tickets are bought via `deposit_for` - so the accounts are recorded
+    let recipients: Vec<(ComponentAddress, u32)> = env.users.iter().map(|user| (user.address, 8u32)).collect();
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(env.owner.address, RRC404_WATER, dec!(40))
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_for",
+                    manifest_args!(lookup.bucket("bucket1"), recipients),
+                )
+            })
+            .deposit_batch(env.owner.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // 2. Owner mints for all the tickets
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "mint",
+                manifest_args!(40u8, 0u8),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Act
+    // 3. Owner pushes the ICE to the users
+    let ticket_ids: Vec<NonFungibleLocalId> = (1u64..=40).map(|id| NonFungibleLocalId::integer(id)).collect();
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "distribute",
+                manifest_args!(ticket_ids),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Assert users got the ICE and still hold the (now claimed) tickets
+    for user in env.users {
+        assert_eq!(dec!(8), test_runner.get_component_balance(user.address, RRC404_ICE));
+        assert_eq!(dec!(8), test_runner.get_component_balance(user.address, test.ticket_address));
+    }
+    let balance_ice = test_runner.get_component_balance(test.ice_randomizer, RRC404_ICE);
+    assert_eq!(dec!(0), balance_ice);
+
+    // 4. Claimed tickets give nothing on withdrawal
+    withdraw_ice(&mut test_runner, test, env.users[0], dec!(8));
+    assert_eq!(dec!(8), test_runner.get_component_balance(env.users[0].address, RRC404_ICE));
+    assert_eq!(dec!(0), test_runner.get_component_balance(env.users[0].address, RRC404_WATER));
+}
+
+#[test]
+fn test_claim_with_proof() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &amounts);
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "mint",
+                manifest_args!(80u8, 0u8),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Act
+    // 1. Users claim with a proof of their tickets
+    for index in 0..amounts.len() {
+        let account = env.users[index];
+        claim_ice(&mut test_runner, test, account, amounts[index]).expect_commit_success();
+
+        let balance_water = test_runner.get_component_balance(account.address, RRC404_WATER);
+        let balance_ice = test_runner.get_component_balance(account.address, RRC404_ICE);
+        let balance_tickets = test_runner.get_component_balance(account.address, test.ticket_address);
+        assert_eq!(amounts[index], balance_ice + balance_water);
+        assert_eq!(amounts[index], balance_tickets);
+    }
+
+    // Assert component is empty
+    let balance_ice = test_runner.get_component_balance(test.ice_randomizer, RRC404_ICE);
+    assert_eq!(dec!(0), balance_ice);
+    let balance_water = test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER);
+    assert_eq!(dec!(0), balance_water);
+
+    // 2. Tickets can't be claimed twice
+    let receipt = claim_ice(&mut test_runner, test, env.users[0], amounts[0]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("has already been claimed"));
+}
+
+#[test]
+fn test_withdraw_as_water() {
+    // Arrange
+    // No idea why, but `advance_to_round_at_timestamp()` requires this custom genesis to succeed.
+    let custom_genesis = CustomGenesis::default(Epoch::of(1), CustomGenesis::default_consensus_manager_config());
+    let mut test_runner = TestRunnerBuilder::new().with_custom_genesis(custom_genesis).without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &amounts);
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "mint",
+                manifest_args!(80u8, 0u8),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Act
+    // 1. A user asks for WATER while the ICE is on cooldown - gets a receipt for the won ICE
+    let user = env.users[4];
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(user.address, test.ticket_address, amounts[4])
+            .take_all_from_worktop(test.ticket_address, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "withdraw_as_water",
+                    manifest_args!(lookup.bucket("bucket1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+    receipt.expect_commit_success();
+
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    let receipt_address = state.receipt_manager.address();
+    assert_eq!(dec!(1), test_runner.get_component_balance(user.address, receipt_address));
+    let balance_water = test_runner.get_component_balance(user.address, RRC404_WATER);
+    assert_eq!(dec!(0), test_runner.get_component_balance(user.address, RRC404_ICE));
+
+    // 2. After the cooldown (and the claim deadline with the sweep) the receipt is exchanged for WATER
+    let now = test_runner.get_current_proposer_timestamp_ms() / 1000;
+    set_claim_deadline(&mut test_runner, test, now + 60 * 60).expect_commit_success();
+    advance_time(&mut test_runner);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "sweep_unclaimed", manifest_args!(1000u32))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(user.address, receipt_address, dec!(1))
+            .take_all_from_worktop(receipt_address, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "redeem_receipts",
+                    manifest_args!(lookup.bucket("bucket1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+    receipt.expect_commit_success();
+
+    // Assert the user got only WATER
+    let balance_water_after = test_runner.get_component_balance(user.address, RRC404_WATER);
+    assert!(balance_water_after > balance_water);
+    assert_eq!(amounts[4], balance_water_after);
+    assert_eq!(dec!(0), test_runner.get_component_balance(user.address, RRC404_ICE));
+    assert_eq!(dec!(0), test_runner.get_component_balance(user.address, receipt_address));
+}
+
+#[test]
+fn test_withdraw_thousands_of_tickets() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(2000)]);
+    for _ in 0..4 {
+        deposit_water(&mut test_runner, test, user, dec!(500));
+    }
+    assert_eq!(dec!(2000), test_runner.get_component_balance(user.address, test.ticket_address));
+
+    // Act
+    // Redeem the unused tickets - more than 255 in each transaction
+    for _ in 0..4 {
+        withdraw_ice(&mut test_runner, test, user, dec!(500));
+    }
+
+    // Assert
+    assert_eq!(dec!(2000), test_runner.get_component_balance(user.address, RRC404_WATER));
+    assert_eq!(dec!(0), test_runner.get_component_balance(user.address, test.ticket_address));
+    assert_eq!(dec!(2000), get_summary(&mut test_runner, test, 1).redeemed);
+    let balance_water = test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER);
+    assert_eq!(dec!(0), balance_water);
+}
+
+#[test]
+fn test_errors_deposit_fractional() {
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(10)]);
+
+    let receipt = deposit(&mut test_runner, test, user, RRC404_WATER, dec!("5.5"), None);
+
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Please do not deposit fractional tokens"));
+}
+
+#[test]
+fn test_errors_withdraw_wrong_resource() {
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(10)]);
+
+    let receipt = call_with_bucket(&mut test_runner, test, user, RRC404_WATER, dec!(10), "withdraw");
+
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Withdrawal requires to burn your tickets."));
+}
+
+#[test]
+fn test_errors_distribute_unknown_tickets() {
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    allocate_tokens(&mut test_runner, test, &[dec!(10)]);
+    deposit_water(&mut test_runner, test, env.users[0], dec!(10));
+
+    for (ticket_id, error) in [
+        (NonFungibleLocalId::integer(999), "Unknown ticket: #999#"),
+        (NonFungibleLocalId::string("ticket").unwrap(), "Invalid ticket ID: <ticket>"),
+    ] {
+        let receipt = test_runner.execute_manifest(
+            ManifestBuilder::new()
+                .lock_fee_from_faucet()
+                .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+                .call_method(
+                    test.ice_randomizer,
+                    "distribute",
+                    manifest_args!(vec![ticket_id]),
+                )
+                .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+        receipt.expect_specific_failure(|e| format!("{:?}", e).contains(error));
+    }
+}
+
+#[test]
+fn test_errors_double_redemption() {
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(10)]);
+    deposit_water(&mut test_runner, test, user, dec!(10));
+
+    // Unused tickets are refunded once
+    claim_ice(&mut test_runner, test, user, dec!(10)).expect_commit_success();
+    assert_eq!(dec!(10), test_runner.get_component_balance(user.address, RRC404_WATER));
+
+    let receipt = claim_ice(&mut test_runner, test, user, dec!(10));
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("has already been claimed"));
+
+    // Burning the claimed tickets gives nothing
+    withdraw_ice(&mut test_runner, test, user, dec!(10));
+    assert_eq!(dec!(10), test_runner.get_component_balance(user.address, RRC404_WATER));
+    let balance_water = test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER);
+    assert_eq!(dec!(0), balance_water);
+}
+
+#[test]
+fn test_sweep_unclaimed() {
+    // Arrange
+    // No idea why, but `advance_to_round_at_timestamp()` requires this custom genesis to succeed.
+    let custom_genesis = CustomGenesis::default(Epoch::of(1), CustomGenesis::default_consensus_manager_config());
+    let mut test_runner = TestRunnerBuilder::new().with_custom_genesis(custom_genesis).without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &amounts);
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+    let now = test_runner.get_current_proposer_timestamp_ms() / 1000;
+    let deadline = now + 60 * 60;
+    let receipt = set_claim_deadline(&mut test_runner, test, now - 60);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("should be in the future"));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "mint",
+                manifest_args!(40u8, 0u8),
+            )
+            .call_method(
+                test.ice_randomizer,
+                "set_claim_deadline",
+                manifest_args!(Some(deadline)),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+    let receipt = set_claim_deadline(&mut test_runner, test, deadline - 60);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("can only be extended"));
+
+    // 1. One user redeems in time
+    withdraw_ice(&mut test_runner, test, env.users[0], amounts[0]);
+    let redeemed = test_runner.get_component_balance(env.users[0].address, RRC404_WATER)
+        + test_runner.get_component_balance(env.users[0].address, RRC404_ICE);
+
+    // Act
+    // 2. Owner sweeps the rest after the deadline
+    advance_time(&mut test_runner);
+    let owner_water = test_runner.get_component_balance(env.owner.address, RRC404_WATER);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "sweep_unclaimed",
+                manifest_args!(1000u32),
+            )
+            .call_method(
+                test.ice_randomizer,
+                "collect_fees",
+                manifest_args!(),
+            )
+            .call_method(
+                test.ice_randomizer,
+                "collect_unclaimed_ice",
+                manifest_args!(),
+            )
+            .deposit_batch(env.owner.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Assert the owner got everything else
+    let swept = test_runner.get_component_balance(env.owner.address, RRC404_WATER) - owner_water
+        + test_runner.get_component_balance(env.owner.address, RRC404_ICE);
+    assert_eq!(sum(&amounts), redeemed + swept);
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    assert_eq!(state.ticket_seq, state.expiry_cursor);
+
+    // 3. Expired tickets can't be redeemed or distributed
+    let receipt = call_with_bucket(&mut test_runner, test, env.users[1], test.ticket_address, amounts[1], "withdraw");
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Tickets have expired at"));
+    let ticket_ids: Vec<NonFungibleLocalId> = (1u64..=10).map(|id| NonFungibleLocalId::integer(id)).collect();
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "distribute", manifest_args!(ticket_ids))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Tickets have expired at"));
+}
+
+#[test]
+fn test_finalize() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &[dec!(150)]);
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[0], amounts[index]);
+    }
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "mint",
+                manifest_args!(40u8, 10u8),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act
+    // 1. Can't finalize while the draw is pending
+    let receipt = finalize(&mut test_runner, test);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Please wait for the pending draws to complete."));
+
+    random_env.execute_next(&mut test_runner, 1);
+    let receipt = finalize(&mut test_runner, test);
+    let summary: CampaignSummary = receipt.expect_commit_success().output(2);
+
+    // Assert
+    assert_eq!(dec!(140), summary.deposited);
+    assert_eq!(40, summary.minted);
+    assert!(summary.finalized_at.is_some());
+
+    // 2. Deposits are closed
+    let receipt = deposit(&mut test_runner, test, env.users[0], RRC404_WATER, dec!(10), None);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Deposits are closed"));
+
+    // 3. The ICE of the finalized round is not melted back
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "melt", manifest_args!())
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    assert_eq!(0, get_summary(&mut test_runner, test, 1).melted);
+
+    // 4. Tickets are still redeemable
+    withdraw_ice(&mut test_runner, test, env.users[0], dec!(140));
+    assert_eq!(dec!(110), test_runner.get_component_balance(env.users[0].address, RRC404_WATER));
+    assert_eq!(dec!(40), test_runner.get_component_balance(env.users[0].address, RRC404_ICE));
+    let summary = get_summary(&mut test_runner, test, 1);
+    assert_eq!(dec!(100), summary.redeemed);
+}
+
+#[test]
+fn test_concurrent_rounds() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &amounts);
+
+    // Act
+    // 1. The first 2 users deposit into round 1, the rest - into round 2
+    for index in 0..2 {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "open_round",
+                manifest_args!(),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    let round_id: u32 = receipt.expect_commit_success().output(2);
+    assert_eq!(2, round_id);
+    for index in 2..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+
+    // 2. Owner draws round 1 only
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "mint_round",
+                manifest_args!(1u32, 80u8, 0u8),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Assert round 1 got all its 50 tickets drawn, round 2 is untouched
+    let balance_ice = test_runner.get_component_balance(test.ice_randomizer, RRC404_ICE);
+    assert_eq!(dec!(50), balance_ice);
+    let summary = get_summary(&mut test_runner, test, 1);
+    assert_eq!(dec!(50), summary.deposited);
+    assert_eq!(50, summary.minted);
+    let summary = get_summary(&mut test_runner, test, 2);
+    assert_eq!(dec!(90), summary.deposited);
+    assert_eq!(0, summary.minted);
+
+    // 3. Round 1 users get ICE, round 2 users get WATER back
+    for index in 0..amounts.len() {
+        let account = env.users[index];
+        withdraw_ice(&mut test_runner, test, account, amounts[index]);
+        let (expected_ice, expected_water) = if index < 2 { (amounts[index], dec!(0)) } else { (dec!(0), amounts[index]) };
+        assert_eq!(expected_ice, test_runner.get_component_balance(account.address, RRC404_ICE));
+        assert_eq!(expected_water, test_runner.get_component_balance(account.address, RRC404_WATER));
+    }
+}
+
+#[test]
+fn test_factory() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    let package_address = test_runner.publish_package_simple(this_package!());
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_function(package_address, "RandomizerFactory", "instantiate", manifest_args!())
+            .build(), vec![]);
+    let factory = receipt.expect_commit_success().new_component_addresses()[0];
+
+    // Act
+    // 1. Someone creates a randomizer for an RRC404 collection
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(
+                factory,
+                "create_randomizer",
+                manifest_args!(test.rrc404_component, RRC404_WATER, RRC404_ICE, dec!(1)),
+            )
+            .deposit_batch(env.users[0].address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.users[0].key)]);
+    let result = receipt.expect_commit_success();
+    let randomizer = result.new_component_addresses()[0];
+    let owner_badge = result.new_resource_addresses()[0];
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(randomizer);
+    assert_eq!(Some(test.rrc404_component), state.rrc404);
+    assert_eq!(dec!(1), state.ticket_price);
+    let ticket_address = state.ticket_manager.address();
+
+    // 2. Its owner brands it and its tickets
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.users[0].address, owner_badge, dec!(1))
+            .set_metadata(randomizer, "name", MetadataValue::String("Frosty Drop".to_string()))
+            .set_metadata(ticket_address, "name", MetadataValue::String("FROSTY".to_string()))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.users[0].key)]);
+    receipt.expect_commit_success();
+
+    // 3. Nobody else can
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .set_metadata(randomizer, "name", MetadataValue::String("Stolen Drop".to_string()))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.users[1].key)]);
+    receipt.expect_auth_failure();
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .set_metadata(ticket_address, "name", MetadataValue::String("STOLEN".to_string()))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.users[1].key)]);
+    receipt.expect_auth_failure();
+
+    // Assert
+    assert_eq!(Some(MetadataValue::String("Frosty Drop".to_string())),
+               test_runner.get_metadata(randomizer.into(), "name"));
+    assert_eq!(Some(MetadataValue::String("FROSTY".to_string())),
+               test_runner.get_metadata(ticket_address.into(), "name"));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(factory, "get_randomizers_count", manifest_args!())
+            .build(), vec![]);
+    let count: u32 = receipt.expect_commit_success().output(1);
+    assert_eq!(1, count);
+}
+
+#[test]
+fn test_lottery() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    let payment = test_runner.create_fungible_resource(dec!(10), DIVISIBILITY_NONE, user.address);
+    // 3 NFTs
+    let prizes = test_runner.create_non_fungible_resource(env.owner.address);
+    let lottery = instantiate_lottery(&mut test_runner, test, payment, prizes, dec!(2));
+
+    // Act
+    // 1. Owner stocks the prizes, the user buys 5 tickets for 2 tokens each
+    stock_prizes(&mut test_runner, lottery, prizes, dec!(3));
+    deposit(&mut test_runner, lottery, user, payment, dec!(10), None).expect_commit_success();
+    assert_eq!(dec!(5), test_runner.get_component_balance(user.address, lottery.ticket_address));
+
+    // 2. Prizes can't be melted
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, lottery.randomizer_owner, dec!(1))
+            .call_method(lottery.ice_randomizer, "mint", manifest_args!(5u8, 1u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Melting is not available in the lottery mode"));
+
+    // 3. Owner draws more than there are prizes
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, lottery.randomizer_owner, dec!(1))
+            .call_method(lottery.ice_randomizer, "mint", manifest_args!(5u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Assert
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(lottery.ice_randomizer);
+    assert_eq!(None, state.rrc404);
+    assert_eq!(dec!(3), test_runner.get_component_balance(lottery.ice_randomizer, prizes));
+    // the payment for the won tickets goes to the treasury
+    assert_eq!(dec!(6), test_runner.get_component_balance(lottery.ice_randomizer, payment));
+
+    withdraw_ice(&mut test_runner, lottery, user, dec!(5));
+    assert_eq!(dec!(3), test_runner.get_component_balance(user.address, prizes));
+    assert_eq!(dec!(4), test_runner.get_component_balance(user.address, payment));
+}
+
+#[test]
+fn test_reroll() {
+    // Arrange
+    let custom_genesis = CustomGenesis::default(Epoch::of(1), CustomGenesis::default_consensus_manager_config());
+    let mut test_runner = TestRunnerBuilder::new().with_custom_genesis(custom_genesis).without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(11)]);
+
+    // 1. The user wins 10 ICE
+    deposit_water(&mut test_runner, test, user, dec!(10));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "mint", manifest_args!(10u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+    withdraw_ice(&mut test_runner, test, user, dec!(10));
+    assert_eq!(dec!(10), test_runner.get_component_balance(user.address, RRC404_ICE));
+    advance_time(&mut test_runner);
+
+    // 2. Rerolls are refused until the Owner sets the fees
+    reroll(&mut test_runner, test, user, dec!(10), dec!(1), dec!(5))
+        .expect_specific_failure(|e| format!("{:?}", e).contains("Rerolls are disabled"));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "set_reroll_fee", manifest_args!(dec!("0.1")))
+            .call_method(test.ice_randomizer, "set_draw_fee", manifest_args!(60u8, dec!(2)))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    let last_draw_at = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer).last_draw_at;
+
+    // Act
+    // 3. The user rerolls the ICE, paying for the draw in XRD
+    let user_xrd = test_runner.get_component_balance(user.address, XRD);
+    reroll(&mut test_runner, test, user, dec!(10), dec!(1), dec!(5)).expect_commit_success();
+    assert_eq!(dec!(0), test_runner.get_component_balance(user.address, RRC404_ICE));
+    assert_eq!(dec!(10), test_runner.get_component_balance(user.address, test.ticket_address));
+    assert_eq!(user_xrd - dec!(2), test_runner.get_component_balance(user.address, XRD));
+    assert_eq!(dec!(2), test_runner.get_component_balance(test.ice_randomizer, XRD));
+    // rerolls don't hold off the keeper draws
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    assert_eq!(last_draw_at, state.last_draw_at);
+    random_env.execute_next(&mut test_runner, 2);
+
+    // 4. The user exchanges the reroll tickets for the new ICE
+    withdraw_ice(&mut test_runner, test, user, dec!(10));
+
+    // Assert
+    assert_eq!(dec!(10), test_runner.get_component_balance(user.address, RRC404_ICE));
+    assert_eq!(dec!(0), test_runner.get_component_balance(user.address, RRC404_WATER));
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    assert_eq!(Some(2), state.reroll_round);
+    assert_eq!(dec!(1), test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER));
+}
+
+#[test]
+fn test_swap_pool() {
+    // Arrange
+    let custom_genesis = CustomGenesis::default(Epoch::of(1), CustomGenesis::default_consensus_manager_config());
+    let mut test_runner = TestRunnerBuilder::new().with_custom_genesis(custom_genesis).without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let amounts = [dec!(5), dec!(5)];
+    allocate_tokens(&mut test_runner, test, &amounts);
+
+    // 1. Both users win 5 ICE
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "mint", manifest_args!(10u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+    for index in 0..amounts.len() {
+        withdraw_ice(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+
+    // Act
+    // 2. Both users put their ICE into the swap pool
+    for index in 0..amounts.len() {
+        let receipt = call_with_bucket(&mut test_runner, test, env.users[index], RRC404_ICE, amounts[index], "deposit_swap");
+        receipt.expect_commit_success();
+    }
+    assert_eq!(dec!(5), test_runner.get_component_balance(env.users[0].address, test.ticket_address));
+
+    // 3. Swap tickets can't be redeemed before the shuffle
+    let receipt = call_with_bucket(&mut test_runner, test, env.users[0], test.ticket_address, dec!(5), "withdraw");
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("can be redeemed after the shuffle"));
+
+    // 4. Unshuffled swap tickets can be cancelled for the pooled ICE
+    let receipt = call_with_bucket(&mut test_runner, test, env.users[0], test.ticket_address, dec!(1), "cancel_swap");
+    receipt.expect_commit_success();
+    assert_eq!(dec!(1), test_runner.get_component_balance(env.users[0].address, RRC404_ICE));
+
+    // 5. Owner shuffles 5 of the 9 pooled ICE
+    shuffle_swaps(&mut test_runner, test, 5).expect_commit_success();
+    random_env.execute_next(&mut test_runner, 2);
+
+    // 6. The claim deadline passes, the sweep leaves the swapped and pooled ICE alone
+    let now = test_runner.get_current_proposer_timestamp_ms() / 1000;
+    set_claim_deadline(&mut test_runner, test, now + 60 * 60).expect_commit_success();
+    advance_time(&mut test_runner);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "sweep_unclaimed", manifest_args!(1000u32))
+            .call_method(test.ice_randomizer, "collect_unclaimed_ice", manifest_args!())
+            .deposit_batch(env.owner.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    assert_eq!(dec!(0), test_runner.get_component_balance(env.owner.address, RRC404_ICE));
+
+    // 7. The rest of the pool is shuffled after the sweep
+    shuffle_swaps(&mut test_runner, test, 5).expect_commit_success();
+    random_env.execute_next(&mut test_runner, 3);
+
+    // Assert
+    for index in 0..amounts.len() {
+        let account = env.users[index];
+        let tickets = test_runner.get_component_balance(account.address, test.ticket_address);
+        withdraw_ice(&mut test_runner, test, account, tickets);
+        assert_eq!(amounts[index], test_runner.get_component_balance(account.address, RRC404_ICE));
+        assert_eq!(dec!(0), test_runner.get_component_balance(account.address, RRC404_WATER));
+    }
+    assert_eq!(dec!(0), test_runner.get_component_balance(test.ice_randomizer, RRC404_ICE));
+}
+
+#[test]
+fn test_rarity_aware_allocation() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    let payment = test_runner.create_fungible_resource(dec!(4), DIVISIBILITY_NONE, user.address);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_non_fungible_resource(
+                OwnerRole::None,
+                NonFungibleIdType::Integer,
+                true,
+                NonFungibleResourceRoles::default(),
+                metadata!(),
+                Some([
+                    (NonFungibleLocalId::integer(1), TieredNft { name: "1".to_string(), rarity: "Legendary".to_string() }),
+                    (NonFungibleLocalId::integer(2), TieredNft { name: "2".to_string(), rarity: "Legendary".to_string() }),
+                    (NonFungibleLocalId::integer(3), TieredNft { name: "3".to_string(), rarity: "Common".to_string() }),
+                    (NonFungibleLocalId::integer(4), TieredNft { name: "4".to_string(), rarity: "Common".to_string() }),
+                ]),
+            )
+            .deposit_batch(env.owner.address)
+            .build(), vec![]);
+    let prizes = receipt.expect_commit_success().new_resource_addresses()[0];
+    let lottery = instantiate_lottery(&mut test_runner, test, payment, prizes, dec!(1));
+    stock_prizes(&mut test_runner, lottery, prizes, dec!(4));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, lottery.randomizer_owner, dec!(1))
+            .call_method(
+                lottery.ice_randomizer,
+                "set_rarity_config",
+                manifest_args!(Some(RarityConfig { field_index: 1, top_tiers: vec!["Legendary".to_string()] })),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act
+    // 1. Anonymous deposits are refused
+    deposit(&mut test_runner, lottery, user, payment, dec!(1), None)
+        .expect_specific_failure(|e| format!("{:?}", e).contains("Deposits should be attributed"));
+
+    // 2. 2 tickets for each of the 2 users, the first user's are split into 2 deposits
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(user.address, payment, dec!(4))
+            .take_from_worktop(payment, dec!(1), "bucket1")
+            .take_from_worktop(payment, dec!(3), "bucket2")
+            .with_name_lookup(|builder, lookup| {
+                builder
+                    .call_method(
+                        lottery.ice_randomizer,
+                        "deposit_for",
+                        manifest_args!(lookup.bucket("bucket1"), vec![(user.address, 1u32)]),
+                    )
+                    .call_method(
+                        lottery.ice_randomizer,
+                        "deposit_for",
+                        manifest_args!(lookup.bucket("bucket2"), vec![(user.address, 1u32), (env.users[1].address, 2u32)]),
+                    )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+    receipt.expect_commit_success();
+
+    // 3. All the prizes are drawn at once
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, lottery.randomizer_owner, dec!(1))
+            .call_method(lottery.ice_randomizer, "mint", manifest_args!(4u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Assert each user got exactly one Legendary
+    for index in 0..2 {
+        let account = env.users[index];
+        withdraw_ice(&mut test_runner, lottery, account, dec!(2));
+        let vault_id = test_runner.get_component_vaults(account.address, prizes)[0];
+        let (amount, ids) = test_runner.inspect_non_fungible_vault(vault_id).unwrap();
+        let ids: Vec<NonFungibleLocalId> = ids.collect();
+        assert_eq!(dec!(2), amount);
+        let legendary = ids.into_iter()
+            .filter(|id| test_runner.get_non_fungible_data::<TieredNft>(prizes, id.clone()).rarity == "Legendary")
+            .count();
+        assert_eq!(1, legendary);
+    }
+}
+
+#[test]
+fn test_allowlist() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let amounts = AMOUNTS;
+    allocate_tokens(&mut test_runner, test, &amounts);
+    let user = env.users[2];
+    let badge = test_runner.create_fungible_resource(dec!(1), DIVISIBILITY_NONE, user.address);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_allowlist",
+                manifest_args!(Some(AllowlistConfig { badge, max_guaranteed: 3 })),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act
+    // 1. Refunded guaranteed tickets free up the allowlist
+    deposit_allowlisted(&mut test_runner, test, user, badge, amounts[2]).expect_commit_success();
+    withdraw_ice(&mut test_runner, test, user, amounts[2]);
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    assert_eq!(0, state.guaranteed_count);
+
+    // 2. The allowlisted user deposits 5 WATER with the badge, the rest - without
+    deposit_allowlisted(&mut test_runner, test, user, badge, amounts[2]).expect_commit_success();
+    for index in [0, 1, 3, 4] {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+
+    // 3. The first draw mints just 3 ICE, marking all of them to be melted
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "mint", manifest_args!(3u8, 3u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // 4. The guaranteed ICE is not melted back
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    assert!(state.melt_list.is_empty());
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "melt", manifest_args!())
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Assert all 3 go to the guaranteed tickets
+    withdraw_ice(&mut test_runner, test, user, amounts[2]);
+    assert_eq!(dec!(3), test_runner.get_component_balance(user.address, RRC404_ICE));
+    assert_eq!(dec!(2), test_runner.get_component_balance(user.address, RRC404_WATER));
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    assert_eq!(3, state.guaranteed_count);
+}
+
+#[test]
+fn test_badge_gated_deposits() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(20)]);
+    // 3 badge NFTs: #1#, #2#, #3#
+    let badge = test_runner.create_non_fungible_resource(user.address);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_deposit_gate",
+                manifest_args!(Some(DepositGate { badge, tickets_per_badge: Some(10) })),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act & Assert
+    // 1. Public deposits are rejected
+    let receipt = deposit(&mut test_runner, test, user, RRC404_WATER, dec!(5), None);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Deposits require a badge"));
+
+    // 2. A badge buys up to 10 tickets
+    deposit_with_badge(&mut test_runner, test, user, badge, 1, dec!(10)).expect_commit_success();
+    let receipt = deposit_with_badge(&mut test_runner, test, user, badge, 1, dec!(5));
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("can buy 0 more tickets"));
+    deposit_with_badge(&mut test_runner, test, user, badge, 2, dec!(5)).expect_commit_success();
+    assert_eq!(dec!(15), test_runner.get_component_balance(user.address, test.ticket_address));
+
+    // 3. The limits are counted per badge NFT, so the proof should be of exactly one
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(user.address, badge, [NonFungibleLocalId::integer(2), NonFungibleLocalId::integer(3)])
+            .pop_from_auth_zone("proof1")
+            .withdraw_from_account(user.address, RRC404_WATER, dec!(5))
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_with_proof",
+                    manifest_args!(lookup.bucket("bucket1"), lookup.proof("proof1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Please present exactly one deposit badge"));
+}
+
+#[test]
+fn test_deposit_limits() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(100)]);
+    let identity_badge = test_runner.create_non_fungible_resource(user.address);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_deposit_limits",
+                manifest_args!(DepositLimits { max_per_call: Some(30), max_per_depositor: Some(40), identity_badge: None }),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("The per-depositor limit requires an identity badge"));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_deposit_limits",
+                manifest_args!(DepositLimits { max_per_call: Some(30), max_per_depositor: Some(40), identity_badge: Some(identity_badge) }),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act & Assert
+    // 1. Deposits without an identity are rejected
+    let receipt = deposit(&mut test_runner, test, user, RRC404_WATER, dec!(5), None);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Deposits are limited per depositor"));
+
+    // 2. Per call limit
+    let receipt = deposit_as(&mut test_runner, test, user, identity_badge, dec!(35));
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Max 30 tickets per deposit"));
+
+    // 3. Per depositor limit
+    deposit_as(&mut test_runner, test, user, identity_badge, dec!(30)).expect_commit_success();
+    let receipt = deposit_as(&mut test_runner, test, user, identity_badge, dec!(20));
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("You can buy 10 more tickets"));
+
+    // 4. Withdrawing unused tickets frees the limit up
+    withdraw_ice(&mut test_runner, test, user, dec!(30));
+    deposit_as(&mut test_runner, test, user, identity_badge, dec!(20)).expect_commit_success();
+    deposit_as(&mut test_runner, test, user, identity_badge, dec!(20)).expect_commit_success();
+    assert_eq!(dec!(40), test_runner.get_component_balance(user.address, test.ticket_address));
+
+    // 5. Behind the deposit gate, both the deposit badge and the identity are presented
+    withdraw_ice(&mut test_runner, test, user, dec!(40));
+    let badge = test_runner.create_non_fungible_resource(user.address);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_deposit_gate",
+                manifest_args!(Some(DepositGate { badge, tickets_per_badge: Some(10) })),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    let receipt = deposit_as(&mut test_runner, test, user, identity_badge, dec!(5));
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Deposits require a badge"));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(user.address, badge, [NonFungibleLocalId::integer(1)])
+            .pop_from_auth_zone("proof1")
+            .create_proof_from_account_of_non_fungibles(user.address, identity_badge, [NonFungibleLocalId::integer(1)])
+            .pop_from_auth_zone("proof2")
+            .withdraw_from_account(user.address, RRC404_WATER, dec!(5))
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_with_proof_as",
+                    manifest_args!(lookup.bucket("bucket1"), lookup.proof("proof1"), lookup.proof("proof2")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+    receipt.expect_commit_success();
+    assert_eq!(dec!(5), test_runner.get_component_balance(user.address, test.ticket_address));
+}
+
+#[test]
+fn test_alternative_payment_token() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (_, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    let token = test_runner.create_fungible_resource(dec!(100), 18, user.address);
+
+    // the stand-in swaps 1 token into 2.1 WATER
+    let package_address = test_runner.compile_and_publish(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/assets/swap_stub"));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(env.owner.address, RRC404_WATER, dec!(100))
+            .take_all_from_worktop(RRC404_WATER, "water")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_function(
+                    package_address,
+                    "SwapStub",
+                    "instantiate",
+                    manifest_args!(lookup.bucket("water"), token, dec!("2.1")),
+                )
+            })
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    let provider = receipt.expect_commit_success().new_component_addresses()[0];
+
+    // Act
+    // 1. Owner accepts the token at 0.5 per WATER
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "set_payment_token",
+                manifest_args!(token, Some(PaymentToken { price: dec!("0.5"), provider })),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // 2. The user pays with the token
+    let receipt = deposit(&mut test_runner, test, user, token, dec!("10.3"), None);
+    receipt.expect_commit_success();
+
+    // Assert 20 tickets for 10 tokens (21 WATER), the rest is returned
+    assert_eq!(dec!(20), test_runner.get_component_balance(user.address, test.ticket_address));
+    assert_eq!(dec!("90.3"), test_runner.get_component_balance(user.address, token));
+    assert_eq!(dec!(1), test_runner.get_component_balance(user.address, RRC404_WATER));
+    assert_eq!(dec!(20), test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER));
+
+    // Unknown tokens are rejected
+    let other = test_runner.create_fungible_resource(dec!(100), 18, user.address);
+    let receipt = deposit(&mut test_runner, test, user, other, dec!(10), None);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Unsupported payment token"));
+}
+
+#[test]
+fn test_referrals() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    let referrer = env.users[1];
+    allocate_tokens(&mut test_runner, test, &[dec!(10)]);
+    let codes = test_runner.create_non_fungible_resource(referrer.address);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "set_referral_bonus", manifest_args!(dec!("0.5"), None::<ResourceAddress>))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Referral rewards require referral codes"));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "set_referral_bonus", manifest_args!(dec!("0.5"), Some(codes)))
+            .withdraw_from_account(env.owner.address, RRC404_WATER, dec!(4))
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "fund_referrals",
+                    manifest_args!(lookup.bucket("bucket1")),
+                )
+            })
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Act
+    // 1. Account referrals are counted, refunded tickets are not
+    let account_referrer = Referrer::Account(referrer.address);
+    deposit(&mut test_runner, test, user, RRC404_WATER, dec!(10), Some(account_referrer.clone())).expect_commit_success();
+    assert_eq!(10, get_referrals(&mut test_runner, test, account_referrer.clone()));
+    withdraw_ice(&mut test_runner, test, user, dec!(10));
+    assert_eq!(0, get_referrals(&mut test_runner, test, account_referrer));
+
+    // 2. The user deposits via the referral code, all 10 tickets win
+    let receipt = deposit(&mut test_runner, test, user, RRC404_WATER, dec!(10), Some(Referrer::Code(NonFungibleLocalId::integer(4))));
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("Unknown referral code"));
+    let code_referrer = Referrer::Code(NonFungibleLocalId::integer(1));
+    deposit(&mut test_runner, test, user, RRC404_WATER, dec!(10), Some(code_referrer.clone())).expect_commit_success();
+    assert_eq!(10, get_referrals(&mut test_runner, test, code_referrer));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "mint", manifest_args!(10u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // 3. The code holder claims the rewards
+    claim_referral_rewards(&mut test_runner, test, referrer, codes).expect_commit_success();
+
+    // Assert the treasury ran out after 8 tickets
+    assert_eq!(dec!(4), test_runner.get_component_balance(referrer.address, RRC404_WATER));
+    assert_eq!(dec!(0), test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER));
+}
+
+#[test]
+fn test_referral_bonus_once_per_ticket() {
+    // Arrange
+    let custom_genesis = CustomGenesis::default(Epoch::of(1), CustomGenesis::default_consensus_manager_config());
+    let mut test_runner = TestRunnerBuilder::new().with_custom_genesis(custom_genesis).without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    let referrer = env.users[1];
+    allocate_tokens(&mut test_runner, test, &[dec!(10)]);
+    let codes = test_runner.create_non_fungible_resource(referrer.address);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "set_referral_bonus", manifest_args!(dec!("0.5"), Some(codes)))
+            .withdraw_from_account(env.owner.address, RRC404_WATER, dec!(10))
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "fund_referrals",
+                    manifest_args!(lookup.bucket("bucket1")),
+                )
+            })
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    let code_referrer = Referrer::Code(NonFungibleLocalId::integer(1));
+    deposit(&mut test_runner, test, user, RRC404_WATER, dec!(10), Some(code_referrer)).expect_commit_success();
+
+    // Act
+    // 1. 6 tickets win, their ICE is marked to be melted back
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "mint", manifest_args!(6u8, 6u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // 2. The ICE is melted back and all 10 tickets win in the next draw
+    advance_time(&mut test_runner);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "melt", manifest_args!())
+            .call_method(test.ice_randomizer, "mint", manifest_args!(10u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 2);
+    claim_referral_rewards(&mut test_runner, test, referrer, codes).expect_commit_success();
+
+    // Assert the 6 tickets that won twice earned the bonus once
+    assert_eq!(dec!(5), test_runner.get_component_balance(referrer.address, RRC404_WATER));
+    assert_eq!(dec!(5), test_runner.get_component_balance(test.ice_randomizer, RRC404_WATER));
+}
+
+#[test]
+fn test_consolation() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let amounts = [dec!(10), dec!(30)];
+    allocate_tokens(&mut test_runner, test, &amounts);
+    let token = test_runner.create_fungible_resource(dec!(100), 18, env.owner.address);
+    let other_token = test_runner.create_fungible_resource(dec!(100), 18, env.owner.address);
+    for index in 0..amounts.len() {
+        deposit_water(&mut test_runner, test, env.users[index], amounts[index]);
+    }
+
+    // Act
+    // 1. Owner funds the consolation and draws 19 of the 40 tickets
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .withdraw_from_account(env.owner.address, token, dec!(100))
+            .take_all_from_worktop(token, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "fund_consolation",
+                    manifest_args!(1u32, lookup.bucket("bucket1")),
+                )
+            })
+            .call_method(test.ice_randomizer, "mint", manifest_args!(19u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // 2. All rounds are consoled with the same token
+    let receipt = fund_consolation(&mut test_runner, test, other_token);
+    receipt.expect_specific_failure(|e| format!("{:?}", e).contains("for all rounds"));
+
+    // 3. The round is finalized with 21 unused tickets, their tokens can't be withdrawn
+    finalize(&mut test_runner, test).expect_commit_success();
+    withdraw_consolation(&mut test_runner, test).expect_commit_success();
+    assert_eq!(dec!(0), test_runner.get_component_balance(env.owner.address, token));
+
+    // 4. Each unused ticket gets 100/21 tokens along with the WATER
+    let share = dec!(100) / dec!(21);
+    let mut total = Decimal::zero();
+    for index in 0..amounts.len() {
+        let account = env.users[index];
+        withdraw_ice(&mut test_runner, test, account, amounts[index]);
+        let water = test_runner.get_component_balance(account.address, RRC404_WATER);
+        let consolation = test_runner.get_component_balance(account.address, token);
+        assert_eq!(water * share, consolation);
+        total += consolation;
+    }
+    assert!(total < dec!(100));
+
+    // 5. Owner takes back the rounding dust
+    withdraw_consolation(&mut test_runner, test).expect_commit_success();
+    assert_eq!(dec!(100), total + test_runner.get_component_balance(env.owner.address, token));
+}
+
+#[test]
+fn test_participant_badges() {
+    // Arrange
+    let mut test_runner = TestRunnerBuilder::new().without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(20)]);
+    deposit_water(&mut test_runner, test, user, dec!(10));
+    let state: IceRandomizerState = test_runner.component_state::<IceRandomizerState>(test.ice_randomizer);
+    let badge_address = state.participant_manager.address();
+
+    // Act
+    // 1. 4 of the 10 tickets win
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "set_participant_badges", manifest_args!(true))
+            .call_method(test.ice_randomizer, "mint", manifest_args!(4u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+    withdraw_ice(&mut test_runner, test, user, dec!(10));
+
+    // Assert one badge for the redemption
+    assert_eq!(dec!(4), test_runner.get_component_balance(user.address, RRC404_ICE));
+    let badges = get_participant_badges(&mut test_runner, user, badge_address);
+    assert_eq!(vec![ParticipantBadge { round: 1, tickets: 10, wins: 4 }], badges);
+
+    // 2. The user deposits into rounds 1 and 2, 2 of the round 2 tickets win
+    deposit_water(&mut test_runner, test, user, dec!(5));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "open_round", manifest_args!())
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    deposit_water(&mut test_runner, test, user, dec!(5));
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "mint_round", manifest_args!(2u32, 2u8, 0u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 2);
+    withdraw_ice(&mut test_runner, test, user, dec!(10));
+
+    // Assert one more badge per round redeemed at once
+    assert_eq!(dec!(6), test_runner.get_component_balance(user.address, RRC404_ICE));
+    let badges = get_participant_badges(&mut test_runner, user, badge_address);
+    assert_eq!(vec![
+        ParticipantBadge { round: 1, tickets: 5, wins: 0 },
+        ParticipantBadge { round: 1, tickets: 10, wins: 4 },
+        ParticipantBadge { round: 2, tickets: 5, wins: 2 },
+    ], badges);
+}
+
+#[test]
+fn test_ticket_status() {
+    // Arrange
+    let custom_genesis = CustomGenesis::default(Epoch::of(1), CustomGenesis::default_consensus_manager_config());
+    let mut test_runner = TestRunnerBuilder::new().with_custom_genesis(custom_genesis).without_trace().build();
+    let env = TestEnv::init(&mut test_runner);
+    let (mut random_env, test) = env.deploy(&mut test_runner);
+    let user = env.users[0];
+    allocate_tokens(&mut test_runner, test, &[dec!(10)]);
+    deposit_water(&mut test_runner, test, user, dec!(10));
+
+    // Act
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "mint", manifest_args!(4u8, 2u8))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+    random_env.execute_next(&mut test_runner, 1);
+
+    // Assert the winners show the draw outcome
+    let tickets: Vec<RandomIceTicket> = (1..=10u64)
+        .map(|id| test_runner.get_non_fungible_data::<RandomIceTicket>(test.ticket_address, NonFungibleLocalId::integer(id)))
+        .collect();
+    let won: Vec<&RandomIceTicket> = tickets.iter().filter(|ticket| ticket.status == TicketStatus::Won).collect();
+    assert_eq!(4, won.len());
+    assert!(won.iter().all(|ticket| ticket.draw == Some(1) && ticket.name.ends_with(": Won")));
+    assert_eq!(6, tickets.iter().filter(|ticket| ticket.status == TicketStatus::Pending && ticket.draw.is_none()).count());
+
+    // Act
+    advance_time(&mut test_runner);
+    let receipt = test_runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "melt", manifest_args!())
+            .build(), vec![NonFungibleGlobalId::from_public_key(&env.owner.key)]);
+    receipt.expect_commit_success();
+
+    // Assert the melted back tickets are pending the draw again
+    let melted: Vec<(u64, RandomIceTicket)> = (1..=10u64)
+        .map(|id| (id, test_runner.get_non_fungible_data::<RandomIceTicket>(test.ticket_address, NonFungibleLocalId::integer(id))))
+        .filter(|(_, ticket)| ticket.status == TicketStatus::MeltedBack)
+        .collect();
+    assert_eq!(2, melted.len());
+    for (id, ticket) in melted {
+        assert_eq!(None, ticket.draw);
+        assert_eq!(None, ticket.result);
+        assert_eq!(format!("IRAND #{}: Melted back", id), ticket.name);
+        assert_eq!("https://ice-rng.radix.live/image/ticket-melted.png", ticket.key_image_url.as_str());
+    }
+
+    // Act
+    claim_ice(&mut test_runner, test, user, dec!(10)).expect_commit_success();
+
+    // Assert
+    for id in 1..=10u64 {
+        let ticket = test_runner.get_non_fungible_data::<RandomIceTicket>(test.ticket_address, NonFungibleLocalId::integer(id));
+        assert_eq!(TicketStatus::Redeemed, ticket.status);
+        assert_eq!(format!("IRAND #{}: Redeemed", id), ticket.name);
+    }
+}
+
 
 fn sum(amounts: &[Decimal]) -> Decimal {
     let mut sum = Decimal::zero();
@@ -458,22 +2160,27 @@ pub fn allocate_tokens(runner: &mut TestRunner<NoExtension, InMemorySubstateData
 }
 
 pub fn deposit_water(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account, amount: Decimal) {
-    let receipt = runner.execute_manifest(
+    let receipt = deposit(runner, test, user, RRC404_WATER, amount, None);
+    let result = receipt.expect_commit_success();
+    result.outcome.expect_success();
+}
+
+pub fn deposit(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account,
+               resource: ResourceAddress, amount: Decimal, referrer: Option<Referrer>) -> TransactionReceipt {
+    return runner.execute_manifest(
         ManifestBuilder::new()
             .lock_fee_from_faucet()
-            .withdraw_from_account(user.address, RRC404_WATER, amount)
-            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .withdraw_from_account(user.address, resource, amount)
+            .take_all_from_worktop(resource, "bucket1")
             .with_name_lookup(|builder, lookup| {
                 builder.call_method(
                     test.ice_randomizer,
                     "deposit",
-                    manifest_args!(lookup.bucket("bucket1")),
+                    manifest_args!(lookup.bucket("bucket1"), referrer),
                 )
             })
             .deposit_batch(user.address)
             .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
-    let result = receipt.expect_commit_success();
-    result.outcome.expect_success();
 }
 
 pub fn withdraw_ice(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account, amount: Decimal) {
@@ -495,6 +2202,289 @@ pub fn withdraw_ice(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabas
     result.outcome.expect_success();
 }
 
+pub fn trigger_mint(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(
+                test.ice_randomizer,
+                "trigger_mint",
+                manifest_args!(),
+            )
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+}
+
+pub fn claim_ice(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account, amount: Decimal) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(user.address, test.ticket_address, amount)
+            .pop_from_auth_zone("proof1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "claim",
+                    manifest_args!(lookup.proof("proof1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+}
+
+pub fn reroll(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account,
+              ice: Decimal, fee: Decimal, xrd: Decimal) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(user.address, RRC404_ICE, ice)
+            .withdraw_from_account(user.address, RRC404_WATER, fee)
+            .withdraw_from_account(user.address, XRD, xrd)
+            .take_all_from_worktop(RRC404_ICE, "ice")
+            .take_all_from_worktop(RRC404_WATER, "fee")
+            .take_all_from_worktop(XRD, "xrd")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "reroll",
+                    manifest_args!(lookup.bucket("ice"), lookup.bucket("fee"), lookup.bucket("xrd")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+}
+
+pub fn set_claim_deadline(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, deadline: i64) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(test.env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "set_claim_deadline", manifest_args!(Some(deadline)))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&test.env.owner.key)]);
+}
+
+pub fn call_with_bucket(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account,
+                        resource: ResourceAddress, amount: Decimal, method: &str) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(user.address, resource, amount)
+            .take_all_from_worktop(resource, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    method,
+                    manifest_args!(lookup.bucket("bucket1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+}
+
+pub fn finalize(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(test.env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(
+                test.ice_randomizer,
+                "finalize",
+                manifest_args!(1u32),
+            )
+            .build(), vec![NonFungibleGlobalId::from_public_key(&test.env.owner.key)]);
+}
+
+pub fn fund_consolation(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, token: ResourceAddress) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(test.env.owner.address, test.randomizer_owner, dec!(1))
+            .withdraw_from_account(test.env.owner.address, token, dec!(100))
+            .take_all_from_worktop(token, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "fund_consolation",
+                    manifest_args!(1u32, lookup.bucket("bucket1")),
+                )
+            })
+            .build(), vec![NonFungibleGlobalId::from_public_key(&test.env.owner.key)]);
+}
+
+pub fn withdraw_consolation(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(test.env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "withdraw_consolation", manifest_args!(1u32))
+            .deposit_batch(test.env.owner.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&test.env.owner.key)]);
+}
+
+/// The participant badges held by the user, ordered by round and tickets.
+pub fn get_participant_badges(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, user: Account,
+                              badge_address: ResourceAddress) -> Vec<ParticipantBadge> {
+    let vault_id = runner.get_component_vaults(user.address, badge_address)[0];
+    let (_, ids) = runner.inspect_non_fungible_vault(vault_id).unwrap();
+    let ids: Vec<NonFungibleLocalId> = ids.collect();
+    let mut badges: Vec<ParticipantBadge> = ids.into_iter()
+        .map(|id| runner.get_non_fungible_data::<ParticipantBadge>(badge_address, id))
+        .collect();
+    badges.sort_by_key(|badge| (badge.round, badge.tickets));
+    return badges;
+}
+
+pub fn shuffle_swaps(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, count: u8) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(test.env.owner.address, test.randomizer_owner, dec!(1))
+            .call_method(test.ice_randomizer, "shuffle_swaps", manifest_args!(count))
+            .build(), vec![NonFungibleGlobalId::from_public_key(&test.env.owner.key)]);
+}
+
+pub fn get_summary(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, round_id: u32) -> CampaignSummary {
+    let receipt = runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(
+                test.ice_randomizer,
+                "get_summary",
+                manifest_args!(round_id),
+            )
+            .build(), vec![]);
+    return receipt.expect_commit_success().output(1);
+}
+
+pub fn claim_referral_rewards(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, referrer: Account,
+                              codes: ResourceAddress) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(referrer.address, codes, [NonFungibleLocalId::integer(1)])
+            .pop_from_auth_zone("proof1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "claim_referral_rewards",
+                    manifest_args!(lookup.proof("proof1")),
+                )
+            })
+            .deposit_batch(referrer.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&referrer.key)]);
+}
+
+pub fn get_referrals(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, referrer: Referrer) -> u32 {
+    let receipt = runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(
+                test.ice_randomizer,
+                "get_referrals",
+                manifest_args!(referrer),
+            )
+            .build(), vec![]);
+    return receipt.expect_commit_success().output(1);
+}
+
+pub fn deposit_allowlisted(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account,
+                           badge: ResourceAddress, amount: Decimal) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(user.address, badge, dec!(1))
+            .pop_from_auth_zone("proof1")
+            .withdraw_from_account(user.address, RRC404_WATER, amount)
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_allowlisted",
+                    manifest_args!(lookup.bucket("bucket1"), lookup.proof("proof1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+}
+
+pub fn deposit_with_badge(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account,
+                          badge: ResourceAddress, badge_id: u64, amount: Decimal) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(user.address, badge, [NonFungibleLocalId::integer(badge_id)])
+            .pop_from_auth_zone("proof1")
+            .withdraw_from_account(user.address, RRC404_WATER, amount)
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_with_proof",
+                    manifest_args!(lookup.bucket("bucket1"), lookup.proof("proof1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+}
+
+pub fn deposit_as(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv, user: Account,
+                  identity_badge: ResourceAddress, amount: Decimal) -> TransactionReceipt {
+    return runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(user.address, identity_badge, [NonFungibleLocalId::integer(1)])
+            .pop_from_auth_zone("proof1")
+            .withdraw_from_account(user.address, RRC404_WATER, amount)
+            .take_all_from_worktop(RRC404_WATER, "bucket1")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    test.ice_randomizer,
+                    "deposit_as",
+                    manifest_args!(lookup.bucket("bucket1"), lookup.proof("proof1")),
+                )
+            })
+            .deposit_batch(user.address)
+            .build(), vec![NonFungibleGlobalId::from_public_key(&user.key)]);
+}
+
+pub fn instantiate_lottery(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, test: DeployedEnv,
+                           payment: ResourceAddress, prizes: ResourceAddress, price: Decimal) -> DeployedEnv {
+    let package_address = runner.publish_package_simple(this_package!());
+    let receipt = runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_function(package_address, "IceRandomizer", "instantiate_lottery", manifest_args!(payment, prizes, price))
+            .deposit_batch(test.env.owner.address)
+            .build(), vec![]);
+    let result = receipt.expect_commit_success();
+    return DeployedEnv {
+        ice_randomizer: result.new_component_addresses()[0],
+        randomizer_owner: result.new_resource_addresses()[0],
+        ticket_address: result.new_resource_addresses()[1],
+        ..test
+    };
+}
+
+pub fn stock_prizes(runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>, lottery: DeployedEnv,
+                    prizes: ResourceAddress, amount: Decimal) {
+    let owner = lottery.env.owner;
+    let receipt = runner.execute_manifest(
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(owner.address, lottery.randomizer_owner, dec!(1))
+            .withdraw_from_account(owner.address, prizes, amount)
+            .take_all_from_worktop(prizes, "prizes")
+            .with_name_lookup(|builder, lookup| {
+                builder.call_method(
+                    lottery.ice_randomizer,
+                    "stock_prizes",
+                    manifest_args!(lookup.bucket("prizes")),
+                )
+            })
+            .build(), vec![NonFungibleGlobalId::from_public_key(&owner.key)]);
+    receipt.expect_commit_success();
+}
+
 
 /// advance time by 4hrs, so later we can melt
 fn advance_time(test_runner: &mut TestRunner<NoExtension, InMemorySubstateDatabase>) {
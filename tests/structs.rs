@@ -1,6 +1,6 @@
-use radix_engine::prelude::{ComponentAddress, NonFungibleVault, ResourceAddress, ScryptoSbor, Secp256k1PublicKey, Vault};
+use radix_engine::prelude::{ComponentAddress, Decimal, NonFungibleGlobalId, NonFungibleLocalId, NonFungibleVault, ResourceAddress, ScryptoSbor, Secp256k1PublicKey, Vault};
 use scrypto::component::KeyValueStore;
-use scrypto::prelude::ResourceManager;
+use scrypto::prelude::{NonFungibleData, ResourceManager, Url};
 use transaction::prelude::*;
 
 #[derive(Copy, Clone)]
@@ -30,12 +30,212 @@ pub struct IceRandomizerState {
     pub ticket_manager: ResourceManager,
 
     pub ticket_seq: u32,
-    pub tickets_by_idx: KeyValueStore<u16, u32>,
-    pub tickets_id_to_idx: KeyValueStore<u32, u16>,
-    pub tickets_count: u16,
+    pub tickets_id_to_idx: KeyValueStore<u32, PoolSlot>,
+    pub rounds: KeyValueStore<u32, Round>,
+    pub round_count: u32,
+    pub current_round: u32,
 
     pub melt_list: Vec<u32>,
 
     pub water: Vault,
     pub ice: NonFungibleVault,
+
+    pub bounty: Vault,
+    pub keeper_config: Option<KeeperConfig>,
+    pub last_draw_at: i64,
+
+    pub fee_vault: Vault,
+    pub expected_fee: u8,
+    pub draw_fee: Decimal,
+    pub fees_locked: Decimal,
+    pub draws: KeyValueStore<u32, DrawRecord>,
+    pub draw_count: u32,
+
+    pub treasury: Vault,
+    pub protocol_fee: Option<ProtocolFee>,
+    pub fee_stage: FeeStage,
+
+    pub receipt_manager: ResourceManager,
+    pub receipt_ice: NonFungibleVault,
+    pub melt_cooldown: i64,
+    pub last_freeze_at: i64,
+
+    pub claim_deadline: Option<i64>,
+    pub ice_treasury: NonFungibleVault,
+    pub expiry_cursor: u32,
+    pub rrc404: Option<ComponentAddress>,
+    pub ticket_price: Decimal,
+    pub prizes: NonFungibleVault,
+    pub reroll_fee: Decimal,
+    pub reroll_round: Option<u32>,
+    pub swap_ice: NonFungibleVault,
+    pub swap_round: Option<u32>,
+    pub swapped_ice: NonFungibleVault,
+    pub deposit_seq: u32,
+    pub rarity: Option<RarityConfig>,
+    pub allowlist: Option<AllowlistConfig>,
+    pub guaranteed_count: u32,
+    pub deposit_gate: Option<DepositGate>,
+    pub badge_tickets: KeyValueStore<NonFungibleLocalId, u32>,
+    pub deposit_limits: DepositLimits,
+    pub depositor_tickets: KeyValueStore<NonFungibleGlobalId, u32>,
+    pub payment_tokens: KeyValueStore<ResourceAddress, PaymentToken>,
+    pub referrals: KeyValueStore<Referrer, u32>,
+    pub referral_bonus: Decimal,
+    pub referral_codes: Option<ResourceAddress>,
+    pub referral_treasury: Vault,
+    pub referral_rewards: KeyValueStore<Referrer, Decimal>,
+    pub referral_payouts: Vault,
+    pub consolation: Option<Vault>,
+    pub participant_manager: ResourceManager,
+    pub participant_badges: bool,
+}
+
+#[derive(ScryptoSbor)]
+pub struct Round {
+    pub tickets_by_idx: KeyValueStore<u16, u32>,
+    pub tickets_count: u16,
+    pub water: Decimal,
+    pub open: bool,
+    pub pending_draws: u32,
+    pub draws: Vec<u32>,
+    pub summary: CampaignSummary,
+    pub guaranteed: Vec<u32>,
+    pub consolation: Decimal,
+    pub consolation_share: Decimal,
+}
+
+#[derive(ScryptoSbor, Clone, Copy, Debug)]
+pub struct PoolSlot {
+    pub round_id: u32,
+    pub idx: u16,
+    pub tracked: bool,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct KeeperConfig {
+    pub threshold: u16,
+    pub cooldown: i64,
+    pub batch_size: u8,
+    pub bounty: Decimal,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct DrawRecord {
+    pub round_id: u32,
+    pub minted: u32,
+    pub fee: Decimal,
+    pub drawn_at: i64,
+    pub tiers: Vec<TierCount>,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct TierCount {
+    pub depositor: Depositor,
+    pub tier: String,
+    pub count: u32,
+}
+
+#[derive(ScryptoSbor, Clone, Debug, PartialEq, Eq)]
+pub enum Depositor {
+    Identity(NonFungibleGlobalId),
+    Account(ComponentAddress),
+    Deposit(u32),
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct RarityConfig {
+    pub field_index: u8,
+    pub top_tiers: Vec<String>,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct AllowlistConfig {
+    pub badge: ResourceAddress,
+    pub max_guaranteed: u32,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct DepositLimits {
+    pub max_per_call: Option<u32>,
+    pub max_per_depositor: Option<u32>,
+    pub identity_badge: Option<ResourceAddress>,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub enum Referrer {
+    Account(ComponentAddress),
+    Code(NonFungibleLocalId),
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct PaymentToken {
+    pub price: Decimal,
+    pub provider: ComponentAddress,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct DepositGate {
+    pub badge: ResourceAddress,
+    pub tickets_per_badge: Option<u32>,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, NonFungibleData)]
+pub struct TieredNft {
+    pub name: String,
+    pub rarity: String,
+}
+
+#[derive(ScryptoSbor, NonFungibleData, Clone, Debug, PartialEq, Eq)]
+pub struct ParticipantBadge {
+    pub round: u32,
+    pub tickets: u32,
+    pub wins: u32,
+}
+
+#[derive(ScryptoSbor, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TicketStatus {
+    Pending,
+    Won,
+    MeltedBack,
+    Redeemed,
+}
+
+#[derive(ScryptoSbor, NonFungibleData, Clone, Debug)]
+pub struct RandomIceTicket {
+    pub result: Option<NonFungibleLocalId>,
+    pub depositor: Option<ComponentAddress>,
+    pub claimed: bool,
+    pub expired: bool,
+    pub round: u32,
+    pub deposit: u32,
+    pub identity: Option<NonFungibleGlobalId>,
+    pub referrer: Option<Referrer>,
+    pub rewarded: bool,
+    pub allowlisted: bool,
+    pub name: String,
+    pub key_image_url: Url,
+    pub status: TicketStatus,
+    pub draw: Option<u32>,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub enum ProtocolFee {
+    Percent(Decimal),
+    Flat(Decimal),
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeStage {
+    Deposit,
+    Withdraw,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct CampaignSummary {
+    pub deposited: Decimal,
+    pub minted: u32,
+    pub melted: u32,
+    pub redeemed: Decimal,
+    pub finalized_at: Option<i64>,
 }